@@ -96,6 +96,13 @@ banana
 
 boundary = b"b1f5d0f0e03874e20b4c5bd2851ec499"
 
+large_file_body = (
+    b"--b1f5d0f0e03874e20b4c5bd2851ec499\r\n"
+    b'Content-Disposition: form-data; name="file"; filename="large.bin"\r\n'
+    b"Content-Type: application/octet-stream\r\n"
+    b"\r\n" + random_data * 1024 + b"\r\n--b1f5d0f0e03874e20b4c5bd2851ec499--\r\n"
+)
+
 
 def bench_multipart(runner: pyperf.Runner):
     runner.bench_func(
@@ -108,7 +115,22 @@ def bench_multipart(runner: pyperf.Runner):
     )
     runner.bench_func(
         "fast_multipart_parser.parse_multipart_form_data",
-        lambda: parse_multipart_form_data(body, boundary, b"UTF-8"),
+        lambda: parse_multipart_form_data(body, boundary, b"UTF-8", line_endings="any"),
+    )
+
+
+def bench_large_file(runner: pyperf.Runner):
+    runner.bench_func(
+        "large file: sanic.request.parse_multipart_form",
+        lambda: sanic_multipart(large_file_body, boundary),
+    )
+    runner.bench_func(
+        "large file: werkzeug.MultiPartParser.parse",
+        lambda: werkzeug_multipart.parse(BytesIO(large_file_body), boundary, len(large_file_body)),
+    )
+    runner.bench_func(
+        "large file: fast_multipart_parser.parse_multipart_form_data",
+        lambda: parse_multipart_form_data(large_file_body, boundary, b"UTF-8"),
     )
 
 
@@ -116,3 +138,4 @@ if __name__ == "__main__":
     runner = pyperf.Runner()
 
     bench_multipart(runner)
+    bench_large_file(runner)
@@ -0,0 +1,413 @@
+use crate::content_header::{
+    ascii_fallback, is_token, parse_parameters_at, write_extended_parameter,
+    write_form_data_parameter, write_parameter, write_quoted_parameter,
+};
+use crate::error::MultipartError;
+use crate::mode::{Mode, Repair};
+use pyo3::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed `Content-Disposition` value such as `form-data; name="file"; filename="a.txt"`.
+///
+/// `filename` and `filename*` are kept apart (RFC 6266), the latter decoded into `filename_ext`.
+/// The RFC 2183 dates are not parsed, they are kept as the strings that were sent.
+///
+/// A `form-data` disposition is written the way HTML5 submits forms: `name` and `filename` are
+/// quoted UTF-8 strings with `"`, CR and LF percent encoded, and no `filename*` is sent (RFC 7578,
+/// section 4.2), the preferred filename is written as `filename` instead.
+#[pyclass(module = "fast_multipart_parser")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDisposition {
+    disposition_type: String,
+    name: Option<String>,
+    filename: Option<String>,
+    filename_ext: Option<String>,
+    creation_date: Option<String>,
+    modification_date: Option<String>,
+    read_date: Option<String>,
+    params: BTreeMap<String, String>,
+}
+
+impl ContentDisposition {
+    /// A disposition of the given type, e.g. `attachment`, without parameters.
+    pub fn new(disposition_type: &str) -> Result<Self, MultipartError> {
+        if !is_token(disposition_type) {
+            return Err(MultipartError::MalformedHeaderValue {
+                reason: "invalid disposition type",
+            });
+        }
+        Ok(Self::without_parameters(
+            disposition_type.to_ascii_lowercase(),
+        ))
+    }
+
+    /// The disposition of a `multipart/form-data` field named `name`.
+    pub fn form_data(name: &str) -> Self {
+        Self {
+            name: Some(name.to_owned()),
+            ..Self::without_parameters(String::from("form-data"))
+        }
+    }
+
+    fn without_parameters(disposition_type: String) -> Self {
+        Self {
+            disposition_type,
+            name: None,
+            filename: None,
+            filename_ext: None,
+            creation_date: None,
+            modification_date: None,
+            read_date: None,
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the filename. A filename that is not printable ASCII is set as `filename_ext`, with
+    /// `filename` as an ASCII fallback in which other characters are replaced with `_`. A
+    /// `form-data` disposition only gets a `filename`, see [`Display`](fmt::Display).
+    pub fn with_filename(mut self, filename: &str) -> Self {
+        if self.disposition_type == "form-data" {
+            self.filename = Some(filename.to_owned());
+            return self;
+        }
+        let fallback = ascii_fallback(filename);
+        self.filename_ext = (fallback != filename).then(|| filename.to_owned());
+        self.filename = Some(fallback);
+        self
+    }
+
+    pub fn parse(value: &str) -> Result<Self, MultipartError> {
+        Self::parse_at(value, Mode::Strict, 0).map(|(disposition, _)| disposition)
+    }
+
+    /// Parses `value` in the given mode, see [`parse_content_header_with_mode`]. What was repaired
+    /// in [`Mode::Lenient`] is returned with offsets into `value`.
+    ///
+    /// [`parse_content_header_with_mode`]: crate::parse_content_header_with_mode
+    pub fn parse_with_mode(value: &str, mode: Mode) -> Result<(Self, Vec<Repair>), MultipartError> {
+        Self::parse_at(value, mode, 0)
+    }
+
+    /// Like [`parse_with_mode`](Self::parse_with_mode), with repair offsets counted from `offset`.
+    pub(crate) fn parse_at(
+        value: &str,
+        mode: Mode,
+        offset: usize,
+    ) -> Result<(Self, Vec<Repair>), MultipartError> {
+        let (disposition_type, mut parameters, repairs) = parse_parameters_at(value, mode, offset)?;
+        if disposition_type.contains('/') {
+            return Err(MultipartError::MalformedHeaderValue {
+                reason: "invalid disposition type",
+            });
+        }
+
+        let filename = parameters.remove("filename").unwrap_or_default();
+        let mut preferred = |name: &str| parameters.remove(name).map(|value| value.preferred());
+        let disposition = Self {
+            disposition_type: disposition_type.to_ascii_lowercase(),
+            name: preferred("name"),
+            filename: filename.plain,
+            filename_ext: filename.extended,
+            creation_date: preferred("creation-date"),
+            modification_date: preferred("modification-date"),
+            read_date: preferred("read-date"),
+            params: parameters
+                .into_iter()
+                .map(|(name, value)| (name, value.preferred()))
+                .collect(),
+        };
+        Ok((disposition, repairs))
+    }
+
+    /// The lowercased disposition type, e.g. `form-data`, `attachment` or `inline`.
+    pub fn disposition_type(&self) -> &str {
+        &self.disposition_type
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The plain `filename` parameter.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// The decoded `filename*` parameter.
+    pub fn filename_ext(&self) -> Option<&str> {
+        self.filename_ext.as_deref()
+    }
+
+    /// `filename*` if it was sent, `filename` otherwise, as RFC 6266 recommends.
+    pub fn preferred_filename(&self) -> Option<&str> {
+        self.filename_ext().or_else(|| self.filename())
+    }
+
+    /// The `creation-date` parameter as sent, an RFC 5322 date that is not parsed.
+    pub fn creation_date_raw(&self) -> Option<&str> {
+        self.creation_date.as_deref()
+    }
+
+    /// The `modification-date` parameter as sent, an RFC 5322 date that is not parsed.
+    pub fn modification_date_raw(&self) -> Option<&str> {
+        self.modification_date.as_deref()
+    }
+
+    /// The `read-date` parameter as sent, an RFC 5322 date that is not parsed.
+    pub fn read_date_raw(&self) -> Option<&str> {
+        self.read_date.as_deref()
+    }
+
+    /// The remaining parameters.
+    pub fn params(&self) -> &BTreeMap<String, String> {
+        &self.params
+    }
+}
+
+impl FromStr for ContentDisposition {
+    type Err = MultipartError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse(value)
+    }
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.disposition_type)?;
+        if self.disposition_type == "form-data" {
+            if let Some(name) = &self.name {
+                write_form_data_parameter(f, "name", name)?;
+            }
+            if let Some(filename) = self.preferred_filename() {
+                write_form_data_parameter(f, "filename", filename)?;
+            }
+        } else {
+            if let Some(name) = &self.name {
+                write_quoted_parameter(f, "name", name)?;
+            }
+            // next to `filename*`, `filename` must not become an extended value itself
+            match (&self.filename, &self.filename_ext) {
+                (Some(filename), Some(_)) => {
+                    write_quoted_parameter(f, "filename", &ascii_fallback(filename))?
+                }
+                (Some(filename), None) => write_quoted_parameter(f, "filename", filename)?,
+                (None, _) => {}
+            }
+            if let Some(filename_ext) = &self.filename_ext {
+                write_extended_parameter(f, "filename", filename_ext)?;
+            }
+        }
+        if let Some(creation_date) = &self.creation_date {
+            write_parameter(f, "creation-date", creation_date)?;
+        }
+        if let Some(modification_date) = &self.modification_date {
+            write_parameter(f, "modification-date", modification_date)?;
+        }
+        if let Some(read_date) = &self.read_date {
+            write_parameter(f, "read-date", read_date)?;
+        }
+        for (name, value) in &self.params {
+            write_parameter(f, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl ContentDisposition {
+    #[new]
+    fn py_new(value: &str) -> PyResult<Self> {
+        Ok(Self::parse(value)?)
+    }
+
+    #[getter(disposition_type)]
+    fn py_disposition_type(&self) -> &str {
+        self.disposition_type()
+    }
+
+    #[getter(name)]
+    fn py_name(&self) -> Option<&str> {
+        self.name()
+    }
+
+    #[getter(filename)]
+    fn py_filename(&self) -> Option<&str> {
+        self.filename()
+    }
+
+    #[getter(filename_ext)]
+    fn py_filename_ext(&self) -> Option<&str> {
+        self.filename_ext()
+    }
+
+    #[getter(preferred_filename)]
+    fn py_preferred_filename(&self) -> Option<&str> {
+        self.preferred_filename()
+    }
+
+    #[getter(creation_date_raw)]
+    fn py_creation_date_raw(&self) -> Option<&str> {
+        self.creation_date_raw()
+    }
+
+    #[getter(modification_date_raw)]
+    fn py_modification_date_raw(&self) -> Option<&str> {
+        self.modification_date_raw()
+    }
+
+    #[getter(read_date_raw)]
+    fn py_read_date_raw(&self) -> Option<&str> {
+        self.read_date_raw()
+    }
+
+    #[getter(params)]
+    fn py_params(&self) -> HashMap<String, String> {
+        self.params.clone().into_iter().collect()
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<ContentDisposition({:?})>", self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_content_disposition() {
+        let disposition: ContentDisposition = "Attachment; filename=\"fallback.txt\"; \
+            FILENAME*=UTF-8''r%C3%A9sum%C3%A9.txt; creation-date=\"Wed, 12 Feb 1997 16:29:51 -0500\"; \
+            read-date=\"Thu, 13 Feb 1997 08:00:00 +0000\"; size=1024"
+            .parse()
+            .unwrap();
+
+        assert_eq!(disposition.disposition_type(), "attachment");
+        assert_eq!(disposition.name(), None);
+        assert_eq!(disposition.filename(), Some("fallback.txt"));
+        assert_eq!(disposition.filename_ext(), Some("résumé.txt"));
+        assert_eq!(disposition.preferred_filename(), Some("résumé.txt"));
+        assert_eq!(
+            disposition.creation_date_raw(),
+            Some("Wed, 12 Feb 1997 16:29:51 -0500")
+        );
+        assert_eq!(disposition.modification_date_raw(), None);
+        assert_eq!(
+            disposition.read_date_raw(),
+            Some("Thu, 13 Feb 1997 08:00:00 +0000")
+        );
+        assert_eq!(
+            disposition.params(),
+            &BTreeMap::from([(String::from("size"), String::from("1024"))])
+        );
+
+        let disposition = ContentDisposition::parse(
+            "form-data; name*=UTF-8''%C3%A9t%C3%A9; filename*0=\"a\"; filename*1=\"b.txt\"",
+        )
+        .unwrap();
+        assert_eq!(disposition.name(), Some("été"));
+        assert_eq!(disposition.filename(), Some("ab.txt"));
+        assert_eq!(disposition.filename_ext(), None);
+        assert_eq!(disposition.preferred_filename(), Some("ab.txt"));
+    }
+
+    #[test]
+    fn test_malformed_content_disposition() {
+        for value in [
+            "",
+            "form-data; name",
+            "text/plain",
+            "form-data; filename*=abc",
+        ] {
+            assert!(
+                matches!(
+                    ContentDisposition::parse(value),
+                    Err(MultipartError::MalformedHeaderValue { .. })
+                ),
+                "{}",
+                value
+            );
+        }
+
+        // lenient parsing skips the parameter without a value and reads the charset-less one
+        for (value, name, filename) in [
+            ("form-data; name; filename=a.txt", None, Some("a.txt")),
+            ("form-data; name=a; filename*=abc", Some("a"), Some("abc")),
+        ] {
+            let (disposition, repairs) =
+                ContentDisposition::parse_with_mode(value, Mode::Lenient).unwrap();
+            assert_eq!(disposition.name(), name);
+            assert_eq!(disposition.preferred_filename(), filename);
+            assert_eq!(repairs.len(), 1, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_display_content_disposition() {
+        for (value, expected) in [
+            ("INLINE", "inline"),
+            (
+                "inline; filename=a.txt; name=\"my \\\"field\\\"\"",
+                "inline; name=\"my \\\"field\\\"\"; filename=\"a.txt\"",
+            ),
+            (
+                "form-data; filename=a.txt; name=\"my field\"",
+                "form-data; name=\"my field\"; filename=\"a.txt\"",
+            ),
+            (
+                "attachment; filename*=UTF-8''na%C3%AFve.txt; filename=\"naive.txt\"",
+                "attachment; filename=\"naive.txt\"; filename*=UTF-8''na%C3%AFve.txt",
+            ),
+            (
+                "attachment; modification-date=\"Wed, 12 Feb 1997 16:29:51 -0500\"; x=\"\"",
+                "attachment; modification-date=\"Wed, 12 Feb 1997 16:29:51 -0500\"; x=\"\"",
+            ),
+        ] {
+            let disposition = ContentDisposition::parse(value).unwrap();
+            assert_eq!(disposition.to_string(), expected);
+            assert_eq!(ContentDisposition::parse(expected).unwrap(), disposition);
+        }
+
+        // a plain filename that is not ASCII is reduced to a fallback, not a second `filename*`
+        let disposition = ContentDisposition::parse(
+            "attachment; filename=\"naïve.txt\"; filename*=UTF-8''na%C3%AFve%0A.txt",
+        )
+        .unwrap();
+        let value = disposition.to_string();
+        assert_eq!(
+            value,
+            "attachment; filename=\"na_ve.txt\"; filename*=UTF-8''na%C3%AFve%0A.txt"
+        );
+        let parsed = ContentDisposition::parse(&value).unwrap();
+        assert_eq!(parsed.filename(), Some("na_ve.txt"));
+        assert_eq!(parsed.filename_ext(), Some("naïve\n.txt"));
+        assert_eq!(parsed.to_string(), value);
+    }
+
+    #[test]
+    fn test_display_form_data() {
+        let disposition =
+            ContentDisposition::form_data("välue \"a\"\r\n\\").with_filename("画像.jpg");
+        assert_eq!(disposition.filename_ext(), None);
+        assert_eq!(
+            disposition.to_string(),
+            "form-data; name=\"välue %22a%22%0D%0A\\\\\"; filename=\"画像.jpg\""
+        );
+
+        let disposition = ContentDisposition::parse(
+            "form-data; name=x; filename=\"a.txt\"; filename*=UTF-8''%C3%A9.txt",
+        )
+        .unwrap();
+        assert_eq!(
+            disposition.to_string(),
+            "form-data; name=\"x\"; filename=\"é.txt\""
+        );
+    }
+}
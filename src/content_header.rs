@@ -1,197 +1,1115 @@
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::borrow::Cow::{Borrowed, Owned};
-use std::borrow::{Borrow, Cow};
-use std::collections::HashMap;
+use crate::content_disposition::ContentDisposition;
+use crate::error::MultipartError;
+use crate::mode::{Mode, Repair};
+use encoding_rs::{Encoding, UTF_8};
+use percent_encoding::{percent_decode, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
 
-lazy_static! {
-    static ref PARENTHESES_RE: Regex = Regex::new(r#"^("|')|("|')$"#).unwrap();
-    static ref ESCAPE_RE: Regex = Regex::new(r#"\\"\s*"#).unwrap();
+/// The `attr-char` set of RFC 8187, everything else is percent encoded in extended values.
+pub(crate) const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// A header value, its parameters and what was repaired to parse them.
+type Repaired<V> = (String, HashMap<String, V>, Vec<Repair>);
+
+fn malformed(reason: &'static str) -> MultipartError {
+    MultipartError::MalformedHeaderValue { reason }
+}
+
+/// Fails with `reason` in strict mode. In lenient mode the malformed parameter is recorded and
+/// parsing goes on.
+fn tolerate(
+    mode: Mode,
+    reason: &'static str,
+    offset: usize,
+    repairs: &mut Vec<Repair>,
+) -> Result<(), MultipartError> {
+    match mode {
+        Mode::Strict => Err(malformed(reason)),
+        Mode::Lenient => {
+            repairs.push(Repair::MalformedParameter { offset, reason });
+            Ok(())
+        }
+    }
+}
+
+/// Whether `byte` is a `tchar` of RFC 9110, section 5.6.2.
+fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(is_token_char)
 }
 
-#[inline]
-fn unescape(value: &str) -> Cow<str> {
-    ESCAPE_RE.replace_all(value, "")
+/// Whether `c` may appear in a quoted string, escaped or not (RFC 9110, section 5.6.4).
+fn is_quoted_char(c: char) -> bool {
+    c == '\t' || !c.is_control()
 }
 
-#[inline]
-fn unquote(value: &str) -> Cow<str> {
-    PARENTHESES_RE.replace_all(value, "")
+struct Tokenizer<'a> {
+    input: &'a str,
+    position: usize,
+    mode: Mode,
+    /// The offset of `input` in the body, for repairs.
+    offset: usize,
+    repairs: Vec<Repair>,
 }
 
-#[inline]
-fn normalize_value(value: &str) -> String {
-    match unquote(value.trim()) {
-        Borrowed(v) => v.to_owned(),
-        Owned(v) => v,
+impl<'a> Tokenizer<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.position).copied()
+    }
+
+    /// Skips spaces and tabs, and in lenient mode the bare line breaks of folded header lines.
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t'))
+            || self.mode == Mode::Lenient && matches!(self.peek(), Some(b'\r' | b'\n'))
+        {
+            self.position += 1;
+        }
+    }
+
+    fn tolerate(&mut self, reason: &'static str, position: usize) -> Result<(), MultipartError> {
+        tolerate(self.mode, reason, self.offset + position, &mut self.repairs)
+    }
+
+    fn skip_to_semicolon(&mut self) {
+        while !matches!(self.peek(), None | Some(b';')) {
+            self.position += 1;
+        }
+    }
+
+    fn token(&mut self) -> Option<&'a str> {
+        let start = self.position;
+        while self.peek().is_some_and(is_token_char) {
+            self.position += 1;
+        }
+        (self.position > start).then(|| &self.input[start..self.position])
+    }
+
+    /// Reads an unquoted parameter value, a token. In lenient mode it is whatever comes before the
+    /// next semicolon, with escaped quotes unescaped and a leading and trailing quote removed, as
+    /// clients send `name='file'` or `name=\"file\"`.
+    fn token_value(&mut self) -> Result<String, MultipartError> {
+        let start = self.position;
+        if self.mode == Mode::Strict {
+            let value = self.token().ok_or(malformed("invalid parameter value"))?;
+            return Ok(value.to_owned());
+        }
+        self.skip_to_semicolon();
+        let value = self.input[start..self.position].trim_end_matches([' ', '\t']);
+        let unescaped = value.replace("\\\"", "\"");
+        let unquoted = unescaped.strip_prefix(['"', '\'']).unwrap_or(&unescaped);
+        let unquoted = unquoted.strip_suffix(['"', '\'']).unwrap_or(unquoted);
+        if !is_token(value) {
+            self.tolerate("invalid parameter value", start)?;
+        } else if unquoted.len() < value.len() {
+            self.repairs.push(Repair::SingleQuotedParameter {
+                offset: self.offset + start,
+            });
+        }
+        Ok(unquoted.to_owned())
+    }
+
+    /// Reads a quoted string starting at the opening quote, resolving quoted pairs. In lenient
+    /// mode only `\"` and `\\` are, other backslashes are kept for clients that do not escape them,
+    /// and so are control characters. An unterminated string runs to the end of the header.
+    fn quoted_string(&mut self) -> Result<String, MultipartError> {
+        let start = self.position + 1;
+        let mut value = String::new();
+        let mut chars = self.input[start..].char_indices().peekable();
+        loop {
+            match chars.next() {
+                Some((index, '"')) => {
+                    self.position = start + index + 1;
+                    return Ok(value);
+                }
+                Some((index, '\\')) => match (chars.peek(), self.mode) {
+                    (Some(&(_, c @ ('"' | '\\'))), _) => {
+                        chars.next();
+                        value.push(c);
+                    }
+                    (Some(&(_, c)), Mode::Strict) if is_quoted_char(c) => {
+                        chars.next();
+                        value.push(c);
+                    }
+                    (_, Mode::Strict) => return Err(malformed("invalid quoted pair")),
+                    (_, Mode::Lenient) => {
+                        self.repairs.push(Repair::BackslashInQuotedString {
+                            offset: self.offset + start + index,
+                        });
+                        value.push('\\');
+                    }
+                },
+                Some((_, c)) if is_quoted_char(c) => value.push(c),
+                Some((index, c)) => {
+                    self.tolerate("control character in quoted string", start + index)?;
+                    value.push(c);
+                }
+                None => {
+                    self.tolerate("unterminated quoted string", start - 1)?;
+                    self.position = self.input.len();
+                    return Ok(value);
+                }
+            }
+        }
     }
 }
 
-pub fn parse_content_header(header: &str) -> (String, HashMap<String, String>) {
-    let unescaped = unescape(header);
-    let normalized_header = unquote(unescaped.borrow());
-    match normalized_header.split_once("; ") {
-        Some((header_value, options_str)) => {
-            let options = HashMap::from_iter(
-                options_str
-                    .split("; ")
-                    .filter_map(|value| value.split_once('='))
-                    .map(|(key, value)| (normalize_value(key), normalize_value(value))),
-            );
-            (normalize_value(header_value), options)
+/// The role of a parameter in RFC 2231: `name*` is an extended value, `name*0` and `name*1*` are
+/// sections of a continued value, the latter percent encoded.
+enum Section {
+    Plain,
+    Extended,
+    Continuation(u32, bool),
+}
+
+fn split_name(name: &str) -> (&str, Section) {
+    let (base, suffix) = match name.split_once('*') {
+        Some(split) => split,
+        None => return (name, Section::Plain),
+    };
+    if suffix.is_empty() {
+        return (base, Section::Extended);
+    }
+    let (index, encoded) = match suffix.strip_suffix('*') {
+        Some(index) => (index, true),
+        None => (suffix, false),
+    };
+    // section numbers are decimal without leading zeros, anything else is an ordinary name
+    match index.parse::<u32>() {
+        Ok(number) if number.to_string() == index => (base, Section::Continuation(number, encoded)),
+        _ => (name, Section::Plain),
+    }
+}
+
+/// Fails with `reason` in strict mode, and records a repair for the parameter in lenient mode.
+type Recover<'a> = dyn FnMut(&'static str) -> Result<(), MultipartError> + 'a;
+
+/// Percent decodes `value`. In lenient mode a `%` that starts no escape is kept.
+fn percent_decode_into(
+    value: &str,
+    output: &mut Vec<u8>,
+    recover: &mut Recover<'_>,
+) -> Result<(), MultipartError> {
+    let bytes = value.as_bytes();
+    let valid = bytes.iter().enumerate().all(|(index, &byte)| {
+        byte != b'%'
+            || bytes
+                .get(index + 1..index + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    });
+    if !valid {
+        recover("invalid percent encoding")?;
+    }
+    output.extend(percent_decode(bytes));
+    Ok(())
+}
+
+/// Splits the `charset'language'` prefix off an extended value. A blank charset means US-ASCII,
+/// which is read as UTF-8, as are unknown or missing charsets in lenient mode.
+fn split_charset<'a>(
+    value: &'a str,
+    recover: &mut Recover<'_>,
+) -> Result<(&'static Encoding, &'a str), MultipartError> {
+    let mut parts = value.splitn(3, '\'');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(""), Some(_), Some(rest)) => Ok((UTF_8, rest)),
+        (Some(charset), Some(_), Some(rest)) => match Encoding::for_label(charset.as_bytes()) {
+            Some(encoding) => Ok((encoding, rest)),
+            None => recover("unknown charset in extended value").map(|_| (UTF_8, rest)),
+        },
+        _ => recover("missing charset in extended value").map(|_| (UTF_8, value)),
+    }
+}
+
+/// The value of a parameter, with the plain and the extended (RFC 2231, RFC 8187) form kept apart.
+#[derive(Debug, Default)]
+pub(crate) struct ParameterValue {
+    pub(crate) plain: Option<String>,
+    pub(crate) extended: Option<String>,
+}
+
+impl ParameterValue {
+    /// The extended value if there is one, the plain value otherwise.
+    pub(crate) fn preferred(self) -> String {
+        self.extended.or(self.plain).unwrap_or_default()
+    }
+}
+
+struct Parameter {
+    /// Where the parameter first appears, for repairs.
+    offset: usize,
+    plain: Option<String>,
+    extended: Option<String>,
+    sections: BTreeMap<u32, (String, bool)>,
+}
+
+impl Parameter {
+    /// Decodes the parameter. A continued value replaces a plain one, and counts as extended if any
+    /// of its sections is percent encoded. In lenient mode an extended value wins over a continued
+    /// one, and the sections of an incomplete continuation are joined.
+    fn resolve(mut self, recover: &mut Recover<'_>) -> Result<ParameterValue, MultipartError> {
+        let extended = match self.extended {
+            Some(extended) => {
+                if !self.sections.is_empty() {
+                    recover("repeated parameter")?;
+                    self.sections.clear();
+                }
+                let (encoding, value) = split_charset(&extended, recover)?;
+                let mut bytes = Vec::new();
+                percent_decode_into(value, &mut bytes, recover)?;
+                Some(encoding.decode_without_bom_handling(&bytes).0.into_owned())
+            }
+            None => None,
+        };
+        if self.sections.is_empty() {
+            return Ok(ParameterValue {
+                plain: self.plain,
+                extended,
+            });
+        }
+        if !self
+            .sections
+            .keys()
+            .copied()
+            .eq(0..self.sections.len() as u32)
+        {
+            recover("incomplete parameter continuation")?;
+        }
+
+        let mut encoding = UTF_8;
+        let mut bytes = Vec::new();
+        let encoded = self.sections.values().any(|(_, encoded)| *encoded);
+        for (index, (value, encoded)) in self.sections.into_values().enumerate() {
+            match (index, encoded) {
+                (0, true) => {
+                    let (charset, value) = split_charset(&value, recover)?;
+                    encoding = charset;
+                    percent_decode_into(value, &mut bytes, recover)?;
+                }
+                (_, true) => percent_decode_into(&value, &mut bytes, recover)?,
+                (_, false) => bytes.extend_from_slice(value.as_bytes()),
+            }
+        }
+        let value = encoding.decode_without_bom_handling(&bytes).0.into_owned();
+        Ok(match encoded {
+            true => ParameterValue {
+                plain: self.plain,
+                extended: Some(value),
+            },
+            false => ParameterValue {
+                plain: Some(value),
+                extended: None,
+            },
+        })
+    }
+}
+
+/// Reassembles RFC 2231 and RFC 8187 extended and continued parameters under their plain names.
+/// In lenient mode the last of repeated parameters wins.
+fn reassemble(
+    parameters: Vec<(String, String, usize)>,
+    mode: Mode,
+    repairs: &mut Vec<Repair>,
+) -> Result<HashMap<String, ParameterValue>, MultipartError> {
+    let mut reassembled: HashMap<String, Parameter> = HashMap::new();
+    for (name, value, offset) in parameters {
+        let (base, section) = split_name(&name);
+        let parameter = reassembled
+            .entry(base.to_owned())
+            .or_insert_with(|| Parameter {
+                offset,
+                plain: None,
+                extended: None,
+                sections: BTreeMap::new(),
+            });
+        let repeated = match section {
+            Section::Plain => parameter.plain.replace(value).is_some(),
+            Section::Extended => parameter.extended.replace(value).is_some(),
+            Section::Continuation(index, encoded) => {
+                parameter.sections.insert(index, (value, encoded)).is_some()
+            }
+        };
+        if repeated {
+            tolerate(mode, "repeated parameter", offset, repairs)?;
+        }
+    }
+    reassembled
+        .into_iter()
+        .map(|(name, parameter)| {
+            let offset = parameter.offset;
+            let value = parameter.resolve(&mut |reason| tolerate(mode, reason, offset, repairs))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Replaces everything but printable ASCII with `_`, for a `filename` sent next to `filename*`.
+pub(crate) fn ascii_fallback(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if matches!(c, ' '..='~') { c } else { '_' })
+        .collect()
+}
+
+/// Writes `; name=value`, quoting the value if it is not a token. Values that cannot be sent in a
+/// quoted string, those with control or non-ASCII characters, are written as an RFC 8187
+/// extended value.
+pub(crate) fn write_parameter(f: &mut fmt::Formatter<'_>, name: &str, value: &str) -> fmt::Result {
+    if is_token(value) {
+        write!(f, "; {}={}", name, value)
+    } else {
+        write_quoted_parameter(f, name, value)
+    }
+}
+
+/// Like [`write_parameter`], but quotes tokens too, as is customary for names and filenames.
+pub(crate) fn write_quoted_parameter(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    value: &str,
+) -> fmt::Result {
+    if value.chars().all(|c| c.is_ascii() && is_quoted_char(c)) {
+        write!(f, "; {}=\"", name)?;
+        for c in value.chars() {
+            if c == '"' || c == '\\' {
+                f.write_char('\\')?;
+            }
+            f.write_char(c)?;
         }
-        None => (String::from(""), HashMap::default()),
+        f.write_char('"')
+    } else {
+        write_extended_parameter(f, name, value)
     }
 }
 
+/// Writes `; name="value"` for `multipart/form-data`, which has no extended values (RFC 7578,
+/// section 4.2). As in HTML5, the value is sent as UTF-8 with `"`, CR and LF percent encoded, and
+/// so are other control characters, which a quoted string cannot hold. `\` is escaped as `\\`
+/// for recipients that resolve quoted pairs.
+pub(crate) fn write_form_data_parameter(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    value: &str,
+) -> fmt::Result {
+    write!(f, "; {}=\"", name)?;
+    for c in value.chars() {
+        match c {
+            '\\' => f.write_str("\\\\")?,
+            '"' => f.write_str("%22")?,
+            c if is_quoted_char(c) => f.write_char(c)?,
+            c => {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    write!(f, "%{:02X}", byte)?;
+                }
+            }
+        }
+    }
+    f.write_char('"')
+}
+
+/// Writes `; name*=UTF-8''value` with the value percent encoded.
+pub(crate) fn write_extended_parameter(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    value: &str,
+) -> fmt::Result {
+    write!(
+        f,
+        "; {}*=UTF-8''{}",
+        name,
+        utf8_percent_encode(value, ATTR_CHAR)
+    )
+}
+
+/// Parses a `Content-Disposition` or `Content-Type` like header value into the disposition or media
+/// type and its parameters, following the grammar of RFC 9110, section 5.6.6.
+///
+/// Parameter names are lowercased, quoted values are unquoted and whitespace is allowed around
+/// semicolons and equals signs. Malformed values, including ones that repeat a parameter, are
+/// rejected rather than guessed at.
+///
+/// Extended (`filename*=UTF-8''na%C3%AFve.txt`) and continued (`filename*0*=UTF-8''na;
+/// filename*1=ive.txt`) parameters of RFC 2231 and RFC 8187 are decoded and returned under their
+/// plain name, taking precedence over a plain parameter of the same name.
+pub fn parse_content_header(
+    header: &str,
+) -> Result<(String, HashMap<String, String>), MultipartError> {
+    let (value, parameters) = parse_parameters(header)?;
+    Ok((
+        value,
+        parameters
+            .into_iter()
+            .map(|(name, value)| (name, value.preferred()))
+            .collect(),
+    ))
+}
+
+/// Like [`parse_content_header`], in the given [`Mode`]. In [`Mode::Lenient`], single-quoted
+/// values are unquoted and backslashes in quoted strings are only taken to escape `"` and `\`.
+/// Malformed parameters are read as well as they can be or skipped, so that only a missing value
+/// is an error. What was repaired is returned with offsets into `header`.
+pub fn parse_content_header_with_mode(
+    header: &str,
+    mode: Mode,
+) -> Result<Repaired<String>, MultipartError> {
+    let (value, parameters, repairs) = parse_parameters_at(header, mode, 0)?;
+    Ok((
+        value,
+        parameters
+            .into_iter()
+            .map(|(name, value)| (name, value.preferred()))
+            .collect(),
+        repairs,
+    ))
+}
+
+/// Formats a `Content-Disposition` value for an HTTP response (RFC 6266), the inverse of
+/// [`parse_content_header`], e.g.
+/// `attachment; filename="na_ve.txt"; filename*=UTF-8''na%C3%AFve.txt`.
+///
+/// `filename` is sent as a quoted string. If it is not printable ASCII, that is an ASCII fallback
+/// in which other characters are replaced with `_`, and the exact filename follows as an RFC 8187
+/// `filename*` parameter. See [`ContentDisposition::with_filename`].
+pub fn format_content_disposition(
+    disposition: &str,
+    filename: Option<&str>,
+) -> Result<String, MultipartError> {
+    let content_disposition = ContentDisposition::new(disposition)?;
+    Ok(match filename {
+        Some(filename) => content_disposition.with_filename(filename).to_string(),
+        None => content_disposition.to_string(),
+    })
+}
+
+/// Like [`parse_content_header`], but keeps the plain and the extended value of each parameter.
+pub(crate) fn parse_parameters(
+    header: &str,
+) -> Result<(String, HashMap<String, ParameterValue>), MultipartError> {
+    parse_parameters_at(header, Mode::Strict, 0).map(|(value, parameters, _)| (value, parameters))
+}
+
+/// Like [`parse_parameters`], in the given mode, with repairs at offsets counted from `offset`.
+pub(crate) fn parse_parameters_at(
+    header: &str,
+    mode: Mode,
+    offset: usize,
+) -> Result<Repaired<ParameterValue>, MultipartError> {
+    let mut tokenizer = Tokenizer {
+        input: header,
+        position: 0,
+        mode,
+        offset,
+        repairs: Vec::new(),
+    };
+    tokenizer.skip_whitespace();
+    let mut value = tokenizer
+        .token()
+        .ok_or(malformed("missing value"))?
+        .to_owned();
+    if tokenizer.peek() == Some(b'/') {
+        tokenizer.position += 1;
+        value.push('/');
+        value.push_str(tokenizer.token().ok_or(malformed("missing subtype"))?);
+    }
+
+    let mut parameters = Vec::new();
+    loop {
+        tokenizer.skip_whitespace();
+        match tokenizer.peek() {
+            None => break,
+            Some(b';') => tokenizer.position += 1,
+            Some(_) => {
+                // lenient parsing skips what is left of the parameter
+                tokenizer.tolerate("expected a semicolon", tokenizer.position)?;
+                tokenizer.skip_to_semicolon();
+                continue;
+            }
+        }
+        tokenizer.skip_whitespace();
+        // empty parameters, as in `a;;b=c` or a trailing semicolon, are allowed
+        if matches!(tokenizer.peek(), None | Some(b';')) {
+            continue;
+        }
+
+        let start = tokenizer.position;
+        let name = match tokenizer.token() {
+            Some(name) => name.to_ascii_lowercase(),
+            None => {
+                tokenizer.tolerate("invalid parameter name", start)?;
+                tokenizer.skip_to_semicolon();
+                continue;
+            }
+        };
+        tokenizer.skip_whitespace();
+        if tokenizer.peek() != Some(b'=') {
+            tokenizer.tolerate("missing parameter value", start)?;
+            tokenizer.skip_to_semicolon();
+            continue;
+        }
+        tokenizer.position += 1;
+        tokenizer.skip_whitespace();
+        let parameter = match tokenizer.peek() {
+            Some(b'"') => tokenizer.quoted_string()?,
+            _ => tokenizer.token_value()?,
+        };
+        parameters.push((name, parameter, offset + start));
+    }
+
+    let mut repairs = tokenizer.repairs;
+    let parameters = reassemble(parameters, mode, &mut repairs)?;
+    repairs.sort_by_key(Repair::offset);
+    Ok((value, parameters, repairs))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashMap;
 
-    #[test]
-    fn test_unescape() {
-        assert_eq!("", unescape("\\\"    "));
-        assert_eq!("", unescape("\\\""));
-        assert_eq!("form-data;", unescape("form-data\\\"          ;"));
-    }
-
-    #[test]
-    fn test_unquote() {
-        assert_eq!("abc", unquote("\"abc\""));
-        assert_eq!("abc", unquote("'abc'"));
-        assert_eq!("abc", unquote("'abc"));
-        assert_eq!("abc", unquote("abc'"));
-        assert_eq!("abc", unquote("\"abc"));
-        assert_eq!("abc", unquote("abc\""));
-        assert_eq!("a''b", unquote("a''b"));
-        assert_eq!("a\"\"b", unquote("a\"\"b"));
+    fn parameters<const N: usize>(pairs: [(&str, &str); N]) -> HashMap<String, String> {
+        pairs
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect()
     }
 
     #[test]
     fn test_parse_content_header_regular_header() {
         let (header_value, options) = parse_content_header(
             "form-data; name=\"attributes\"; filename=\"test-attribute_5.tsv\"",
-        );
-
-        assert_eq!(header_value, String::from("form-data"));
-        assert_eq!(
-            options,
-            HashMap::from_iter([
-                (String::from("name"), String::from("attributes")),
-                (
-                    String::from("filename"),
-                    String::from("test-attribute_5.tsv")
-                )
-            ])
         )
-    }
-
-    #[test]
-    fn test_parse_content_header_escaped_non_ascii_1() {
-        let (header_value, options) =
-            parse_content_header(r#"form-data; name=\"你好\"; filename=\"file abc.txt\""#);
+        .unwrap();
 
         assert_eq!(header_value, String::from("form-data"));
         assert_eq!(
             options,
-            HashMap::from([
-                (String::from("name"), String::from("你好")),
-                (String::from("filename"), String::from("file abc.txt"))
-            ])
+            parameters([("name", "attributes"), ("filename", "test-attribute_5.tsv")])
         );
 
-        let (header_value, options) =
-            parse_content_header(r#"form-data; name=\"কখগ\"; filename=\"你好.txt\""#);
-
-        assert_eq!(header_value, String::from("form-data"));
         assert_eq!(
-            options,
-            HashMap::from([
-                (String::from("name"), String::from("কখগ")),
-                (String::from("filename"), String::from("你好.txt"))
-            ])
+            parse_content_header("form-data").unwrap(),
+            (String::from("form-data"), HashMap::new())
+        );
+        assert_eq!(
+            parse_content_header("text/plain; charset=utf-8").unwrap(),
+            (
+                String::from("text/plain"),
+                parameters([("charset", "utf-8")])
+            )
         );
+    }
 
-        let (header_value, options) =
-            parse_content_header(r#"form-data; name=\"কখগ-你好\"; filename=\"কখগ-你好.txt\""#);
+    #[test]
+    fn test_parse_content_header_non_ascii() {
+        for (header, name, filename) in [
+            (
+                "form-data; name=\"你好\"; filename=\"file abc.txt\"",
+                "你好",
+                "file abc.txt",
+            ),
+            (
+                "form-data; name=\"কখগ\"; filename=\"你好.txt\"",
+                "কখগ",
+                "你好.txt",
+            ),
+            (
+                "form-data; name=\"কখগ-你好\"; filename=\"কখগ-你好.txt\"",
+                "কখগ-你好",
+                "কখগ-你好.txt",
+            ),
+        ] {
+            let (header_value, options) = parse_content_header(header).unwrap();
 
-        assert_eq!(header_value, String::from("form-data"));
-        assert_eq!(
-            options,
-            HashMap::from([
-                (String::from("name"), String::from("কখগ-你好")),
-                (String::from("filename"), String::from("কখগ-你好.txt"))
-            ])
-        )
+            assert_eq!(header_value, String::from("form-data"));
+            assert_eq!(
+                options,
+                parameters([("name", name), ("filename", filename)])
+            );
+        }
     }
 
     #[test]
     fn test_parse_content_header_unquoted() {
         let (header_value, options) =
-            parse_content_header(r#"form-data; name=my_field; filename=file-name.txt"#);
+            parse_content_header(r#"form-data; name=my_field; filename=file-name.txt"#).unwrap();
 
         assert_eq!(header_value, String::from("form-data"));
         assert_eq!(
             options,
-            HashMap::from([
-                (String::from("name"), String::from("my_field")),
-                (String::from("filename"), String::from("file-name.txt"))
-            ])
+            parameters([("name", "my_field"), ("filename", "file-name.txt")])
         )
     }
 
     #[test]
     fn test_parse_content_header_quoted() {
-        let (header_value, options) = parse_content_header("form-data; name=\"my;f;ield");
+        for (header, expected) in [
+            (
+                r#"form-data; name=my_field; filename="file;name.txt""#,
+                parameters([("name", "my_field"), ("filename", "file;name.txt")]),
+            ),
+            (
+                r#"form-data; name=";"; filename=";""#,
+                parameters([("name", ";"), ("filename", ";")]),
+            ),
+            // quoted pairs keep the escaped character, they used to be dropped as `myfieldname`
+            (
+                r#"form-data; name="my\"field\"name""#,
+                parameters([("name", "my\"field\"name")]),
+            ),
+            (
+                r#"form-data; name="back\\slash\x""#,
+                parameters([("name", "back\\slashx")]),
+            ),
+            (
+                r#"form-data; name=""; filename=" padded ""#,
+                parameters([("name", ""), ("filename", " padded ")]),
+            ),
+        ] {
+            let (header_value, options) = parse_content_header(header).unwrap();
 
-        assert_eq!(header_value, String::from("form-data"));
+            assert_eq!(header_value, String::from("form-data"));
+            assert_eq!(options, expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_content_header_whitespace_and_case() {
+        for header in [
+            "form-data;name=\"x\";filename=a.txt",
+            "  form-data \t;\tname = \"x\" ;  filename=a.txt  ",
+            "form-data; NAME=\"x\"; FileName=a.txt;",
+            "form-data;; name=\"x\" ;;filename=a.txt",
+        ] {
+            assert_eq!(
+                parse_content_header(header).unwrap(),
+                (
+                    String::from("form-data"),
+                    parameters([("name", "x"), ("filename", "a.txt")])
+                ),
+                "{}",
+                header
+            );
+        }
+    }
+
+    /// Asserts that each header is rejected with the given reason.
+    fn assert_malformed<const N: usize>(cases: [(&str, &str); N]) {
+        for (header, expected) in cases {
+            match parse_content_header(header) {
+                Err(MultipartError::MalformedHeaderValue { reason }) => {
+                    assert_eq!(reason, expected, "{}", header)
+                }
+                other => panic!("{}: {:?}", header, other),
+            }
+        }
+    }
+
+    /// Parses `header` in lenient mode, returning the parameters and what was repaired.
+    fn lenient(header: &str) -> (HashMap<String, String>, Vec<Repair>) {
+        let (_, parameters, repairs) = parse_content_header_with_mode(header, Mode::Lenient)
+            .unwrap_or_else(|error| panic!("{}: {:?}", header, error));
+        (parameters, repairs)
+    }
+
+    fn malformed_parameter(offset: usize, reason: &'static str) -> Repair {
+        Repair::MalformedParameter { offset, reason }
+    }
+
+    #[test]
+    fn test_unescape() {
+        // backslash-escaped quotes outside a quoted string are only removed in lenient mode
+        assert_malformed([
+            ("form-data; name=\\\"    ", "invalid parameter value"),
+            ("form-data; name=\\\"", "invalid parameter value"),
+            ("form-data\\\"          ;", "expected a semicolon"),
+        ]);
+
+        for header in ["form-data; name=\\\"    ", "form-data; name=\\\""] {
+            assert_eq!(
+                lenient(header),
+                (
+                    parameters([("name", "")]),
+                    vec![malformed_parameter(16, "invalid parameter value")]
+                )
+            );
+        }
         assert_eq!(
-            options,
-            HashMap::from([(String::from("name"), String::from("my;f;ield")),])
+            parse_content_header_with_mode("form-data\\\"          ;", Mode::Lenient).unwrap(),
+            (
+                String::from("form-data"),
+                HashMap::new(),
+                vec![malformed_parameter(9, "expected a semicolon")]
+            )
         );
+    }
 
-        let (header_value, options) =
-            parse_content_header(r#"form-data; name=my_field; filename="file;name.txt""#);
+    #[test]
+    fn test_unquote() {
+        for (value, expected) in [
+            ("\"abc\"", "abc"),
+            // single quotes are token characters, only lenient parsing strips them
+            ("'abc'", "'abc'"),
+            ("'abc", "'abc"),
+            ("abc'", "abc'"),
+            ("a''b", "a''b"),
+        ] {
+            let (_, options) = parse_content_header(&format!("form-data; name={}", value)).unwrap();
+            assert_eq!(options, parameters([("name", expected)]), "{}", value);
+        }
 
-        assert_eq!(header_value, String::from("form-data"));
+        assert_malformed([
+            ("form-data; name=\"abc", "unterminated quoted string"),
+            ("form-data; name=abc\"", "expected a semicolon"),
+            ("form-data; name=a\"\"b", "expected a semicolon"),
+        ]);
+
+        for (value, repairs) in [
+            ("\"abc\"", vec![]),
+            ("'abc'", vec![Repair::SingleQuotedParameter { offset: 16 }]),
+            ("'abc", vec![Repair::SingleQuotedParameter { offset: 16 }]),
+            ("abc'", vec![Repair::SingleQuotedParameter { offset: 16 }]),
+            (
+                "\"abc",
+                vec![malformed_parameter(16, "unterminated quoted string")],
+            ),
+            (
+                "abc\"",
+                vec![malformed_parameter(16, "invalid parameter value")],
+            ),
+        ] {
+            assert_eq!(
+                lenient(&format!("form-data; name={}", value)),
+                (parameters([("name", "abc")]), repairs),
+                "{}",
+                value
+            );
+        }
         assert_eq!(
-            options,
-            HashMap::from([
-                (String::from("name"), String::from("my_field")),
-                (String::from("filename"), String::from("file;name.txt"))
-            ])
+            lenient("form-data; name=a''b"),
+            (parameters([("name", "a''b")]), vec![])
         );
+        assert_eq!(
+            lenient("form-data; name=a\"\"b"),
+            (
+                parameters([("name", "a\"\"b")]),
+                vec![malformed_parameter(16, "invalid parameter value")]
+            )
+        );
+    }
 
-        let (header_value, options) =
-            parse_content_header(r#"form-data; name=; filename=filename.txt"#);
+    #[test]
+    fn test_parse_content_header_backslashes() {
+        let header = r#"form-data; filename="C:\Users\x \"1\".txt\\""#;
 
-        assert_eq!(header_value, String::from("form-data"));
+        let (_, options) = parse_content_header(header).unwrap();
+        assert_eq!(options, parameters([("filename", r#"C:Usersx "1".txt\"#)]));
+
+        // clients that do not escape backslashes get them back, only `\"` and `\\` are escapes
+        let (_, options, repairs) = parse_content_header_with_mode(header, Mode::Lenient).unwrap();
         assert_eq!(
             options,
-            HashMap::from([
-                (String::from("name"), String::from("")),
-                (String::from("filename"), String::from("filename.txt"))
-            ])
+            parameters([("filename", r#"C:\Users\x "1".txt\"#)])
+        );
+        assert_eq!(
+            repairs,
+            [
+                Repair::BackslashInQuotedString { offset: 23 },
+                Repair::BackslashInQuotedString { offset: 29 }
+            ]
         );
 
-        let (header_value, options) = parse_content_header(r#"form-data; name=";"; filename=";""#);
+        assert_malformed([("form-data; name=\"a\\\u{7}\"", "invalid quoted pair")]);
+    }
 
-        assert_eq!(header_value, String::from("form-data"));
+    #[test]
+    fn test_parse_content_header_escaped_non_ascii_1() {
+        for (header, name, filename) in [
+            (
+                r#"form-data; name=\"你好\"; filename=\"file abc.txt\""#,
+                "你好",
+                "file abc.txt",
+            ),
+            (
+                r#"form-data; name=\"কখগ\"; filename=\"你好.txt\""#,
+                "কখগ",
+                "你好.txt",
+            ),
+            (
+                r#"form-data; name=\"কখগ-你好\"; filename=\"কখগ-你好.txt\""#,
+                "কখগ-你好",
+                "কখগ-你好.txt",
+            ),
+        ] {
+            // quotes escaped outside a quoted string are unescaped in lenient mode only
+            assert_malformed([(header, "invalid parameter value")]);
+
+            let (options, repairs) = lenient(header);
+            assert_eq!(
+                options,
+                parameters([("name", name), ("filename", filename)])
+            );
+            assert_eq!(repairs.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_parse_content_header_malformed() {
+        assert_malformed([
+            ("", "missing value"),
+            ("; name=x", "missing value"),
+            ("text/", "missing subtype"),
+            ("form-data name=x", "expected a semicolon"),
+            ("form-data; name=\"x\" y", "expected a semicolon"),
+            ("form-data; name=\"x", "unterminated quoted string"),
+            ("form-data; name=\"x\\", "invalid quoted pair"),
+            (
+                "form-data; name=\"a\u{7}b\"",
+                "control character in quoted string",
+            ),
+            ("form-data; =x", "invalid parameter name"),
+            ("form-data; name", "missing parameter value"),
+            ("form-data; name=", "invalid parameter value"),
+            (
+                "form-data; name=; filename=a.txt",
+                "invalid parameter value",
+            ),
+            ("form-data; filename=你好.txt", "invalid parameter value"),
+            ("form-data; filename=a b.txt", "expected a semicolon"),
+            ("form-data; name=a; Name=b", "repeated parameter"),
+        ]);
+
+        for (header, expected) in [
+            ("form-data name=x", parameters([])),
+            ("form-data; name=\"x\" y", parameters([("name", "x")])),
+            ("form-data; name=\"x", parameters([("name", "x")])),
+            ("form-data; name=\"x\\", parameters([("name", "x\\")])),
+            (
+                "form-data; name=\"a\u{7}b\"",
+                parameters([("name", "a\u{7}b")]),
+            ),
+            ("form-data; =x", parameters([])),
+            ("form-data; name", parameters([])),
+            ("form-data; name=", parameters([("name", "")])),
+            (
+                "form-data; name=; filename=a.txt",
+                parameters([("name", ""), ("filename", "a.txt")]),
+            ),
+            (
+                "form-data; filename=你好.txt",
+                parameters([("filename", "你好.txt")]),
+            ),
+            (
+                "form-data; filename=a b.txt",
+                parameters([("filename", "a b.txt")]),
+            ),
+            ("form-data; name=a; Name=b", parameters([("name", "b")])),
+        ] {
+            let (options, repairs) = lenient(header);
+            assert_eq!(options, expected, "{}", header);
+            assert!(!repairs.is_empty(), "{}", header);
+        }
+        for header in ["", "text/"] {
+            assert!(parse_content_header_with_mode(header, Mode::Lenient).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_content_header_extended_values() {
+        for (header, expected) in [
+            (
+                "attachment; filename*=UTF-8''Na%C3%AFve%20file.jpg",
+                parameters([("filename", "Naïve file.jpg")]),
+            ),
+            (
+                "attachment; filename*=utf-8'en'Na%C3%AFve%20file.jpg",
+                parameters([("filename", "Naïve file.jpg")]),
+            ),
+            (
+                "attachment; filename*=iso-8859-1''caf%E9.txt",
+                parameters([("filename", "café.txt")]),
+            ),
+            (
+                "attachment; filename*=''plain.txt",
+                parameters([("filename", "plain.txt")]),
+            ),
+            (
+                "form-data; name*=UTF-8''%E5%90%8D%E5%89%8D; name=\"fallback\"",
+                parameters([("name", "名前")]),
+            ),
+            (
+                "form-data; filename=\"fallback.txt\"; FILENAME*=UTF-8''r%C3%A9sum%C3%A9.txt",
+                parameters([("filename", "résumé.txt")]),
+            ),
+            (
+                "form-data; name=a*b; a*b=c",
+                parameters([("name", "a*b"), ("a*b", "c")]),
+            ),
+        ] {
+            assert_eq!(
+                parse_content_header(header).unwrap(),
+                (header.split(';').next().unwrap().to_owned(), expected),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_content_header_continuations() {
+        for (header, expected) in [
+            (
+                "attachment; filename*0*=UTF-8''Na%C3%AF; filename*1*=ve%20file; filename*2=.jpg",
+                "Naïve file.jpg",
+            ),
+            (
+                "attachment; filename*1=\"second \"; filename*0=\"first \"; filename*2=third",
+                "first second third",
+            ),
+            (
+                "attachment; filename=\"fallback\"; filename*0=\"long\"; filename*1=\"name\"",
+                "longname",
+            ),
+            (
+                "attachment; filename*0*=iso-8859-1''caf%E9; filename*1=.txt",
+                "café.txt",
+            ),
+        ] {
+            let (_, options) = parse_content_header(header).unwrap();
+            assert_eq!(options, parameters([("filename", expected)]), "{}", header);
+        }
+
+        let (_, options) = parse_content_header(
+            "form-data; name*0=\"my\"; name*1*=%20field; filename*00=a; filename*x=b",
+        )
+        .unwrap();
         assert_eq!(
             options,
-            HashMap::from([
-                (String::from("name"), String::from(";")),
-                (String::from("filename"), String::from(";"))
+            parameters([
+                ("name", "my field"),
+                ("filename*00", "a"),
+                ("filename*x", "b")
             ])
         );
+    }
+
+    #[test]
+    fn test_parse_content_header_malformed_extended_values() {
+        assert_malformed([
+            (
+                "attachment; filename*=Na%C3%AFve.jpg",
+                "missing charset in extended value",
+            ),
+            (
+                "attachment; filename*=klingon''abc",
+                "unknown charset in extended value",
+            ),
+            (
+                "attachment; filename*=UTF-8''100%",
+                "invalid percent encoding",
+            ),
+            (
+                "attachment; filename*=UTF-8''%zz",
+                "invalid percent encoding",
+            ),
+            (
+                "attachment; filename*0=a; filename*2=c",
+                "incomplete parameter continuation",
+            ),
+            (
+                "attachment; filename*1=b",
+                "incomplete parameter continuation",
+            ),
+            (
+                "attachment; filename*0=a; filename*0*=b",
+                "repeated parameter",
+            ),
+            (
+                "attachment; filename*=UTF-8''a; filename*0=b",
+                "repeated parameter",
+            ),
+            (
+                "attachment; filename*=UTF-8''a; FileName*=UTF-8''b",
+                "repeated parameter",
+            ),
+        ]);
 
-        let (header_value, options) = parse_content_header(r#"form-data; name="my\"field\"name""#);
+        for (header, expected) in [
+            ("attachment; filename*=Na%C3%AFve.jpg", "Naïve.jpg"),
+            ("attachment; filename*=klingon''abc", "abc"),
+            ("attachment; filename*=UTF-8''100%", "100%"),
+            ("attachment; filename*=UTF-8''%zz", "%zz"),
+            ("attachment; filename*0=a; filename*2=c", "ac"),
+            ("attachment; filename*1=b", "b"),
+            ("attachment; filename*0=a; filename*0*=UTF-8''b", "b"),
+            ("attachment; filename*=UTF-8''a; filename*0=b", "a"),
+            ("attachment; filename*=UTF-8''a; FileName*=UTF-8''b", "b"),
+        ] {
+            let (options, repairs) = lenient(header);
+            assert_eq!(options, parameters([("filename", expected)]), "{}", header);
+            assert!(
+                matches!(repairs[..], [Repair::MalformedParameter { .. }]),
+                "{}",
+                header
+            );
+        }
+    }
 
-        assert_eq!(header_value, String::from("form-data"));
-        assert_eq!(
-            options,
-            HashMap::from([(String::from("name"), String::from("myfieldname")),])
-        )
+    #[test]
+    fn test_format_content_disposition() {
+        for (disposition, filename, expected) in [
+            ("attachment", None, "attachment"),
+            ("Inline", Some("report.pdf"), "inline; filename=\"report.pdf\""),
+            (
+                "attachment",
+                Some("my \"report\".pdf"),
+                "attachment; filename=\"my \\\"report\\\".pdf\"",
+            ),
+            (
+                "attachment",
+                Some("naïve résumé.pdf"),
+                "attachment; filename=\"na_ve r_sum_.pdf\"; filename*=UTF-8''na%C3%AFve%20r%C3%A9sum%C3%A9.pdf",
+            ),
+            (
+                "attachment",
+                Some("100%\\a\r\n.txt"),
+                "attachment; filename=\"100%\\\\a__.txt\"; filename*=UTF-8''100%25%5Ca%0D%0A.txt",
+            ),
+            (
+                "attachment",
+                Some("画像.jpg"),
+                "attachment; filename=\"__.jpg\"; filename*=UTF-8''%E7%94%BB%E5%83%8F.jpg",
+            ),
+        ] {
+            let value = format_content_disposition(disposition, filename).unwrap();
+            assert_eq!(value, expected);
+
+            let (parsed, options) = parse_content_header(&value).unwrap();
+            assert_eq!(parsed, disposition.to_ascii_lowercase());
+            assert_eq!(options.get("filename").map(String::as_str), filename);
+        }
+
+        for disposition in ["", "attachment; x=y", "a b", "text/plain"] {
+            assert!(matches!(
+                format_content_disposition(disposition, Some("a.txt")),
+                Err(MultipartError::MalformedHeaderValue {
+                    reason: "invalid disposition type"
+                })
+            ));
+        }
     }
 }
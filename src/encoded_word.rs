@@ -0,0 +1,133 @@
+use crate::transfer_encoding::{self, hex_value, TransferEncoding};
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+
+/// Decodes the text of a `Q` encoded-word, in which `_` stands for a space.
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'_' => output.push(b' '),
+            b'=' => {
+                let high = hex_value(*bytes.get(index + 1)?)?;
+                let low = hex_value(*bytes.get(index + 2)?)?;
+                output.push(high << 4 | low);
+                index += 2;
+            }
+            byte => output.push(byte),
+        }
+        index += 1;
+    }
+    Some(output)
+}
+
+/// Decodes the encoded-word at the start of `input`, returning the text and the length of the word.
+fn decode_word(input: &str) -> Option<(String, usize)> {
+    let mut parts = input.strip_prefix("=?")?.splitn(3, '?');
+    let label = parts.next()?;
+    let encoding = parts.next()?;
+    let rest = parts.next()?;
+    let text = &rest[..rest.find("?=")?];
+    if [label, encoding, text]
+        .iter()
+        .any(|part| part.contains(|c: char| c.is_ascii_whitespace()))
+    {
+        return None;
+    }
+
+    // RFC 2231 allows a language after the charset, as in `=?UTF-8*en?Q?...?=`
+    let charset = Encoding::for_label(label.split('*').next()?.as_bytes())?;
+    let bytes = match encoding {
+        "B" | "b" => {
+            transfer_encoding::decode(TransferEncoding::Base64, text.as_bytes(), 0).ok()?
+        }
+        "Q" | "q" => decode_q(text)?,
+        _ => return None,
+    };
+    // `=?`, the three parts with the `?` after each of them, and `=`
+    let length = label.len() + encoding.len() + text.len() + 6;
+    Some((
+        charset.decode_without_bom_handling(&bytes).0.into_owned(),
+        length,
+    ))
+}
+
+/// Decodes the RFC 2047 `B` and `Q` encoded-words in a header value, e.g.
+/// `=?UTF-8?B?55S75YOPLmpwZw==?=`. Words that cannot be decoded, for example because of an unknown
+/// charset, are kept as they are. Whitespace between two encoded-words is removed, so that a text
+/// split into several words is joined again.
+pub(crate) fn decode_encoded_words(value: &str) -> Cow<'_, str> {
+    if !value.contains("=?") {
+        return Cow::Borrowed(value);
+    }
+
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let before = &rest[..start];
+        match decode_word(&rest[start..]) {
+            Some((decoded, length)) => {
+                if !(after_word && before.chars().all(|c| c.is_ascii_whitespace())) {
+                    output.push_str(before);
+                }
+                output.push_str(&decoded);
+                rest = &rest[start + length..];
+                after_word = true;
+            }
+            None => {
+                output.push_str(&rest[..start + 2]);
+                rest = &rest[start + 2..];
+                after_word = false;
+            }
+        }
+    }
+    output.push_str(rest);
+    Cow::Owned(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_encoded_words() {
+        for (value, expected) in [
+            ("=?UTF-8?B?55S75YOPLmpwZw==?=", "画像.jpg"),
+            ("=?utf-8?b?55S75YOPLmpwZw?=", "画像.jpg"),
+            ("=?ISO-8859-1?Q?caf=E9_cr=E8me.txt?=", "café crème.txt"),
+            ("=?UTF-8*fr?Q?caf=C3=A9?=", "café"),
+            (
+                "report =?UTF-8?Q?r=C3=A9sum=C3=A9?= final",
+                "report résumé final",
+            ),
+            ("=?UTF-8?Q?a?= =?UTF-8?Q?b?=\t =?UTF-8?Q?_c?=", "ab c"),
+            ("=?UTF-8?Q?a?= plain =?UTF-8?Q?b?=", "a plain b"),
+            ("no words here", "no words here"),
+        ] {
+            assert_eq!(decode_encoded_words(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_undecodable_words_are_kept() {
+        for value in [
+            "=?klingon?Q?abc?=",
+            "=?UTF-8?X?abc?=",
+            "=?UTF-8?B?!!!?=",
+            "=?UTF-8?Q?=ZZ?=",
+            "=?UTF-8?Q?a b?=",
+            "=?UTF-8?Q?unterminated",
+            "a=?b",
+            "=?",
+        ] {
+            assert_eq!(decode_encoded_words(value), value);
+        }
+        assert_eq!(
+            decode_encoded_words("=?bad?Q?x?= =?UTF-8?Q?ok?="),
+            "=?bad?Q?x?= ok"
+        );
+    }
+}
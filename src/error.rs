@@ -0,0 +1,131 @@
+use crate::limits::Limit;
+#[cfg(feature = "async")]
+use crate::stream::BoxError;
+use crate::transfer_encoding::TransferEncoding;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// The errors that can occur while parsing a multipart body.
+///
+/// Offsets are byte offsets into the body at which the problem was detected.
+#[derive(Debug)]
+pub enum MultipartError {
+    InvalidBoundary {
+        reason: &'static str,
+    },
+    /// The `Content-Type` of the body is not a multipart one or lacks the boundary.
+    InvalidContentType {
+        reason: &'static str,
+    },
+    MissingClosingDelimiter {
+        offset: usize,
+    },
+    MalformedHeader {
+        offset: usize,
+    },
+    /// A `Content-Disposition` or `Content-Type` like value does not follow RFC 9110.
+    MalformedHeaderValue {
+        reason: &'static str,
+    },
+    MissingContentDisposition {
+        offset: usize,
+    },
+    MissingFieldName {
+        offset: usize,
+    },
+    /// A part has a `Content-Disposition` other than `form-data` (only in strict mode).
+    InvalidDisposition {
+        offset: usize,
+    },
+    /// A line of a `text/plain` form body can be read in more than one way (only in strict mode).
+    AmbiguousFormData {
+        offset: usize,
+    },
+    /// A limit of [`ParserLimits`](crate::ParserLimits) was crossed. The body size is checked
+    /// before the input is parsed, so for [`Limit::BodySize`] the offset is `max`, that of the
+    /// first byte past the limit, rather than the position the parser had reached.
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+        offset: usize,
+    },
+    MalformedEncoding {
+        encoding: TransferEncoding,
+        offset: usize,
+    },
+    /// Writing a spooled file failed.
+    Io(io::Error),
+    #[cfg(feature = "async")]
+    Stream(BoxError),
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::InvalidBoundary { reason } => write!(f, "invalid boundary: {}", reason),
+            MultipartError::InvalidContentType { reason } => {
+                write!(f, "invalid content type: {}", reason)
+            }
+            MultipartError::MissingClosingDelimiter { offset } => {
+                write!(
+                    f,
+                    "body ended without a closing delimiter at byte {}",
+                    offset
+                )
+            }
+            MultipartError::MalformedHeader { offset } => {
+                write!(f, "malformed part header at byte {}", offset)
+            }
+            MultipartError::MalformedHeaderValue { reason } => {
+                write!(f, "malformed header value: {}", reason)
+            }
+            MultipartError::MissingContentDisposition { offset } => {
+                write!(
+                    f,
+                    "part at byte {} has no Content-Disposition header",
+                    offset
+                )
+            }
+            MultipartError::MissingFieldName { offset } => {
+                write!(f, "part at byte {} has no field name", offset)
+            }
+            MultipartError::InvalidDisposition { offset } => {
+                write!(f, "part at byte {} is not form-data", offset)
+            }
+            MultipartError::AmbiguousFormData { offset } => {
+                write!(f, "ambiguous form data at byte {}", offset)
+            }
+            MultipartError::LimitExceeded { limit, max, offset } => {
+                write!(f, "{} of {} exceeded at byte {}", limit, max, offset)
+            }
+            MultipartError::MalformedEncoding { encoding, offset } => {
+                write!(
+                    f,
+                    "malformed {} content in part at byte {}",
+                    encoding, offset
+                )
+            }
+            MultipartError::Io(error) => write!(f, "error spooling file: {}", error),
+            #[cfg(feature = "async")]
+            MultipartError::Stream(error) => write!(f, "error reading body: {}", error),
+        }
+    }
+}
+
+impl Error for MultipartError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MultipartError::Io(error) => Some(error),
+            #[cfg(feature = "async")]
+            MultipartError::Stream(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MultipartError {
+    fn from(error: io::Error) -> Self {
+        MultipartError::Io(error)
+    }
+}
@@ -0,0 +1,210 @@
+use crate::mode::Repair;
+use crate::multipart::{Field, FieldHead, JsonPolicy};
+use encoding_rs::Encoding;
+use pyo3::exceptions::PyKeyError;
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyString};
+
+/// The fields of a parsed form, in the order they were sent.
+///
+/// Unlike a map, repeated names (e.g. `<input type="file" multiple>`) keep every value.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FormData {
+    fields: Vec<(String, Field)>,
+    repairs: Vec<Repair>,
+}
+
+impl FormData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, name: String, field: Field) {
+        self.fields.push((name, field));
+    }
+
+    /// Returns the first field with the given name.
+    pub fn get(&self, name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, field)| field)
+    }
+
+    /// Returns every field with the given name, in wire order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Field> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, field)| field)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields.iter().any(|(key, _)| key == name)
+    }
+
+    /// Iterates over all `(name, field)` pairs in wire order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Field)> {
+        self.fields
+            .iter()
+            .map(|(name, field)| (name.as_str(), field))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// The deviations from the RFCs that were tolerated while parsing, see [`crate::Mode`].
+    pub fn repairs(&self) -> &[Repair] {
+        &self.repairs
+    }
+
+    /// Adds a field of a form body without headers per field, such as an urlencoded one. The name
+    /// and value are decoded with `encoding`, and the value is only parsed as JSON with
+    /// [`JsonPolicy::Always`].
+    pub(crate) fn push_value(
+        &mut self,
+        name: &[u8],
+        value: &[u8],
+        encoding: &'static Encoding,
+        json: JsonPolicy,
+    ) {
+        let (name, ..) = encoding.decode(name);
+        let field = Field::text(
+            FieldHead::text_plain(),
+            value,
+            encoding,
+            json == JsonPolicy::Always,
+        );
+        self.push(name.into_owned(), field);
+    }
+
+    pub(crate) fn add_repairs(&mut self, repairs: impl IntoIterator<Item = Repair>) {
+        self.repairs.extend(repairs);
+        self.repairs.sort_by_key(Repair::offset);
+    }
+}
+
+impl IntoIterator for FormData {
+    type Item = (String, Field);
+    type IntoIter = std::vec::IntoIter<(String, Field)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.into_iter()
+    }
+}
+
+impl FromIterator<(String, Field)> for FormData {
+    fn from_iter<T: IntoIterator<Item = (String, Field)>>(iter: T) -> Self {
+        Self {
+            fields: iter.into_iter().collect(),
+            repairs: Vec::new(),
+        }
+    }
+}
+
+impl IntoPy<PyObject> for FormData {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        MultiDict {
+            items: self
+                .fields
+                .into_iter()
+                .map(|(name, field)| (name, field.into_py(py)))
+                .collect(),
+            repairs: self.repairs.iter().map(Repair::to_string).collect(),
+        }
+        .into_py(py)
+    }
+}
+
+/// The Python counterpart of [`FormData`].
+///
+/// Follows the semantics of `multidict.MultiDict`: indexing and `get` return the first value for a
+/// key, `get_all` returns every value, and iteration yields every pair in wire order.
+#[pyclass(module = "fast_multipart_parser")]
+pub struct MultiDict {
+    items: Vec<(String, PyObject)>,
+    repairs: Vec<String>,
+}
+
+#[pymethods]
+impl MultiDict {
+    fn __getitem__(&self, py: Python<'_>, key: &str) -> PyResult<PyObject> {
+        self.items
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone_ref(py))
+            .ok_or_else(|| PyKeyError::new_err(key.to_owned()))
+    }
+
+    #[args(default = "None")]
+    fn get(&self, py: Python<'_>, key: &str, default: Option<PyObject>) -> PyObject {
+        self.__getitem__(py, key)
+            .unwrap_or_else(|_| default.unwrap_or_else(|| py.None()))
+    }
+
+    /// Descriptions of the deviations from the RFCs that were tolerated while parsing.
+    #[getter]
+    fn repairs(&self) -> Vec<String> {
+        self.repairs.clone()
+    }
+
+    fn get_all(&self, py: Python<'_>, key: &str) -> Vec<PyObject> {
+        self.items
+            .iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value.clone_ref(py))
+            .collect()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.items.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn values(&self, py: Python<'_>) -> Vec<PyObject> {
+        self.items
+            .iter()
+            .map(|(_, value)| value.clone_ref(py))
+            .collect()
+    }
+
+    fn items(&self, py: Python<'_>) -> Vec<(String, PyObject)> {
+        self.items
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone_ref(py)))
+            .collect()
+    }
+
+    fn __contains__(&self, key: &str) -> bool {
+        self.items.iter().any(|(name, _)| name == key)
+    }
+
+    fn __len__(&self) -> usize {
+        self.items.len()
+    }
+
+    fn __iter__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        Ok(PyList::new(py, self.keys())
+            .call_method0("__iter__")?
+            .into_py(py))
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let items = self
+            .items
+            .iter()
+            .map(|(name, value)| {
+                Ok(format!(
+                    "{}: {}",
+                    PyString::new(py, name).repr()?,
+                    value.as_ref(py).repr()?
+                ))
+            })
+            .collect::<PyResult<Vec<String>>>()?;
+        Ok(format!("<MultiDict({})>", items.join(", ")))
+    }
+}
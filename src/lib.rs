@@ -1,34 +1,542 @@
 extern crate core;
 
+mod content_disposition;
 mod content_header;
+mod encoded_word;
+mod error;
+mod form_data;
+mod limits;
+mod media_type;
+mod mode;
 mod multipart;
+mod parser;
+mod part;
+mod plain;
+mod spool;
+#[cfg(feature = "async")]
+mod stream;
+mod transfer_encoding;
+mod urlencoded;
+mod writer;
 
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use pythonize::depythonize;
 use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
 
-pub use content_header::parse_content_header as _parse_content_header;
-pub use multipart::{parse_multipart_form_data as _parse_multipart_form_data, Field};
+pub use content_disposition::ContentDisposition;
+pub use content_header::{
+    format_content_disposition as _format_content_disposition,
+    parse_content_header as _parse_content_header, parse_content_header_with_mode,
+};
+pub use error::MultipartError;
+pub use form_data::{FormData, MultiDict};
+pub use limits::{Limit, ParserLimits};
+pub use media_type::MediaType;
+pub use mode::{LineEndings, Mode, Repair};
+pub use multipart::{
+    parse_multipart as _parse_multipart, parse_multipart_content_type,
+    parse_multipart_form_data as _parse_multipart_form_data,
+    parse_multipart_form_data_with_options as _parse_multipart_form_data_with_options, Field,
+    JsonPolicy, ParseOptions, UploadFile,
+};
+pub use parser::{Event, MultipartParser};
+#[cfg(feature = "bytes")]
+pub use part::{BytesPart, BytesParts};
+pub use part::{Part, PartHeaders, Parts};
+pub use plain::{
+    parse_text_plain_form as _parse_text_plain_form, parse_text_plain_form_with_options,
+};
+pub use spool::{FileContent, SpooledFile};
+#[cfg(feature = "async")]
+pub use stream::{AsyncMultipart, AsyncPart, BoxError};
+pub use transfer_encoding::TransferEncoding;
+pub use urlencoded::{
+    parse_urlencoded_form as _parse_urlencoded_form, parse_urlencoded_form_with_options,
+};
+pub use writer::{MultipartReader, MultipartWriter};
 
-#[pyfunction]
-#[pyo3(text_signature = "(header, /)")]
-fn parse_content_header(header: &str) -> PyResult<(String, HashMap<String, String>)> {
-    Ok(_parse_content_header(header))
+// the exception macros of pyo3 0.17 check a cfg that newer compilers do not know about
+#[allow(unexpected_cfgs)]
+mod exceptions {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyValueError;
+
+    create_exception!(fast_multipart_parser, MultipartError, PyValueError);
+    create_exception!(fast_multipart_parser, InvalidBoundaryError, MultipartError);
+    create_exception!(
+        fast_multipart_parser,
+        InvalidContentTypeError,
+        MultipartError
+    );
+    create_exception!(
+        fast_multipart_parser,
+        MissingClosingDelimiterError,
+        MultipartError
+    );
+    create_exception!(fast_multipart_parser, MalformedHeaderError, MultipartError);
+    create_exception!(
+        fast_multipart_parser,
+        MissingContentDispositionError,
+        MultipartError
+    );
+    create_exception!(fast_multipart_parser, MissingFieldNameError, MultipartError);
+    create_exception!(
+        fast_multipart_parser,
+        InvalidDispositionError,
+        MultipartError
+    );
+    create_exception!(
+        fast_multipart_parser,
+        AmbiguousFormDataError,
+        MultipartError
+    );
+    create_exception!(fast_multipart_parser, LimitExceededError, MultipartError);
+    create_exception!(
+        fast_multipart_parser,
+        MalformedEncodingError,
+        MultipartError
+    );
+}
+
+impl From<MultipartError> for PyErr {
+    fn from(error: MultipartError) -> Self {
+        let message = error.to_string();
+        match error {
+            MultipartError::InvalidBoundary { .. } => {
+                exceptions::InvalidBoundaryError::new_err(message)
+            }
+            MultipartError::InvalidContentType { .. } => {
+                exceptions::InvalidContentTypeError::new_err(message)
+            }
+            MultipartError::MissingClosingDelimiter { .. } => {
+                exceptions::MissingClosingDelimiterError::new_err(message)
+            }
+            MultipartError::MalformedHeader { .. }
+            | MultipartError::MalformedHeaderValue { .. } => {
+                exceptions::MalformedHeaderError::new_err(message)
+            }
+            MultipartError::MissingContentDisposition { .. } => {
+                exceptions::MissingContentDispositionError::new_err(message)
+            }
+            MultipartError::MissingFieldName { .. } => {
+                exceptions::MissingFieldNameError::new_err(message)
+            }
+            MultipartError::InvalidDisposition { .. } => {
+                exceptions::InvalidDispositionError::new_err(message)
+            }
+            MultipartError::AmbiguousFormData { .. } => {
+                exceptions::AmbiguousFormDataError::new_err(message)
+            }
+            MultipartError::LimitExceeded { .. } => {
+                exceptions::LimitExceededError::new_err(message)
+            }
+            MultipartError::MalformedEncoding { .. } => {
+                exceptions::MalformedEncodingError::new_err(message)
+            }
+            MultipartError::Io(error) => error.into(),
+            #[cfg(feature = "async")]
+            MultipartError::Stream(_) => exceptions::MultipartError::new_err(message),
+        }
+    }
+}
+
+#[pyfunction("*", mode = "\"strict\"")]
+#[pyo3(text_signature = "(header, /, *, mode=\"strict\")")]
+fn parse_content_header(header: &str, mode: &str) -> PyResult<(String, HashMap<String, String>)> {
+    let (value, parameters, _) = parse_content_header_with_mode(header, parse_mode(mode)?)?;
+    Ok((value, parameters))
+}
+
+#[pyfunction(filename = "None", "*", disposition = "\"attachment\"")]
+#[pyo3(text_signature = "(filename=None, /, *, disposition=\"attachment\")")]
+fn format_content_disposition(filename: Option<&str>, disposition: &str) -> PyResult<String> {
+    Ok(_format_content_disposition(disposition, filename)?)
+}
+
+fn parse_mode(mode: &str) -> PyResult<Mode> {
+    match mode {
+        "strict" => Ok(Mode::Strict),
+        "lenient" => Ok(Mode::Lenient),
+        _ => Err(PyValueError::new_err(
+            "mode must be either \"strict\" or \"lenient\"",
+        )),
+    }
+}
+
+fn json_policy(json: &str) -> PyResult<JsonPolicy> {
+    match json {
+        "never" => Ok(JsonPolicy::Never),
+        "content-type" => Ok(JsonPolicy::ContentType),
+        "always" => Ok(JsonPolicy::Always),
+        _ => Err(PyValueError::new_err(
+            "json must be one of \"never\", \"content-type\" or \"always\"",
+        )),
+    }
+}
+
+/// Builds the options from the keyword arguments shared by the parse functions.
+#[allow(clippy::too_many_arguments)]
+fn parse_options(
+    max_body_size: Option<usize>,
+    max_parts: Option<usize>,
+    max_header_size: Option<usize>,
+    max_headers: Option<usize>,
+    max_field_name_length: Option<usize>,
+    max_field_size: Option<usize>,
+    max_file_size: Option<usize>,
+    max_files: Option<usize>,
+    spool_threshold: Option<usize>,
+    spool_dir: Option<PathBuf>,
+    max_nesting_depth: usize,
+    mode: &str,
+    line_endings: &str,
+    json: &str,
+    decode_encoded_words: bool,
+) -> PyResult<ParseOptions> {
+    let line_endings = match line_endings {
+        "crlf" => LineEndings::Crlf,
+        "any" => LineEndings::Any,
+        _ => {
+            return Err(PyValueError::new_err(
+                "line_endings must be either \"crlf\" or \"any\"",
+            ))
+        }
+    };
+    Ok(ParseOptions {
+        limits: ParserLimits {
+            max_body_size,
+            max_parts,
+            max_header_size,
+            max_headers,
+            max_field_name_length,
+            max_field_size,
+            max_file_size,
+            max_files,
+        },
+        spool_threshold,
+        spool_dir,
+        max_nesting_depth,
+        mode: parse_mode(mode)?,
+        line_endings,
+        json: json_policy(json)?,
+        decode_encoded_words,
+    })
 }
 
-#[pyfunction]
-#[pyo3(text_signature = "(body, boundary, charset, /)")]
+// the defaults mirror `ParserLimits::DEFAULT` and `ParseOptions::default()`
+#[pyfunction(
+    "*",
+    max_body_size = "None",
+    max_parts = "1000",
+    max_header_size = "16384",
+    max_headers = "32",
+    max_field_name_length = "1024",
+    max_field_size = "1048576",
+    max_file_size = "None",
+    max_files = "1000",
+    spool_threshold = "None",
+    spool_dir = "None",
+    max_nesting_depth = "1",
+    mode = "\"lenient\"",
+    line_endings = "\"crlf\"",
+    json = "\"content-type\"",
+    decode_encoded_words = "true"
+)]
+#[pyo3(
+    text_signature = "(body, boundary, charset, /, *, max_body_size=None, max_parts=1000, max_header_size=16384, max_headers=32, max_field_name_length=1024, max_field_size=1048576, max_file_size=None, max_files=1000, spool_threshold=None, spool_dir=None, max_nesting_depth=1, mode=\"lenient\", line_endings=\"crlf\", json=\"content-type\", decode_encoded_words=True)"
+)]
+#[allow(clippy::too_many_arguments)]
 fn parse_multipart_form_data<'a>(
     body: &'a [u8],
     boundary: &'a [u8],
     charset: &'a [u8],
-) -> PyResult<HashMap<String, Field>> {
-    Ok(_parse_multipart_form_data(body, boundary, charset))
+    max_body_size: Option<usize>,
+    max_parts: Option<usize>,
+    max_header_size: Option<usize>,
+    max_headers: Option<usize>,
+    max_field_name_length: Option<usize>,
+    max_field_size: Option<usize>,
+    max_file_size: Option<usize>,
+    max_files: Option<usize>,
+    spool_threshold: Option<usize>,
+    spool_dir: Option<PathBuf>,
+    max_nesting_depth: usize,
+    mode: &str,
+    line_endings: &str,
+    json: &str,
+    decode_encoded_words: bool,
+) -> PyResult<FormData> {
+    let options = parse_options(
+        max_body_size,
+        max_parts,
+        max_header_size,
+        max_headers,
+        max_field_name_length,
+        max_field_size,
+        max_file_size,
+        max_files,
+        spool_threshold,
+        spool_dir,
+        max_nesting_depth,
+        mode,
+        line_endings,
+        json,
+        decode_encoded_words,
+    )?;
+    Ok(_parse_multipart_form_data_with_options(
+        body, boundary, charset, &options,
+    )?)
+}
+
+#[pyfunction(
+    "*",
+    max_body_size = "None",
+    max_parts = "1000",
+    max_header_size = "16384",
+    max_headers = "32",
+    max_field_name_length = "1024",
+    max_field_size = "1048576",
+    max_file_size = "None",
+    max_files = "1000",
+    spool_threshold = "None",
+    spool_dir = "None",
+    max_nesting_depth = "1",
+    mode = "\"lenient\"",
+    line_endings = "\"crlf\"",
+    json = "\"content-type\"",
+    decode_encoded_words = "true"
+)]
+#[pyo3(
+    text_signature = "(body, content_type, /, *, max_body_size=None, max_parts=1000, max_header_size=16384, max_headers=32, max_field_name_length=1024, max_field_size=1048576, max_file_size=None, max_files=1000, spool_threshold=None, spool_dir=None, max_nesting_depth=1, mode=\"lenient\", line_endings=\"crlf\", json=\"content-type\", decode_encoded_words=True)"
+)]
+#[allow(clippy::too_many_arguments)]
+fn parse_multipart(
+    body: &[u8],
+    content_type: &str,
+    max_body_size: Option<usize>,
+    max_parts: Option<usize>,
+    max_header_size: Option<usize>,
+    max_headers: Option<usize>,
+    max_field_name_length: Option<usize>,
+    max_field_size: Option<usize>,
+    max_file_size: Option<usize>,
+    max_files: Option<usize>,
+    spool_threshold: Option<usize>,
+    spool_dir: Option<PathBuf>,
+    max_nesting_depth: usize,
+    mode: &str,
+    line_endings: &str,
+    json: &str,
+    decode_encoded_words: bool,
+) -> PyResult<FormData> {
+    let options = parse_options(
+        max_body_size,
+        max_parts,
+        max_header_size,
+        max_headers,
+        max_field_name_length,
+        max_field_size,
+        max_file_size,
+        max_files,
+        spool_threshold,
+        spool_dir,
+        max_nesting_depth,
+        mode,
+        line_endings,
+        json,
+        decode_encoded_words,
+    )?;
+    Ok(_parse_multipart(body, content_type, &options)?)
+}
+
+#[pyfunction(
+    "*",
+    max_body_size = "None",
+    max_parts = "1000",
+    max_field_name_length = "1024",
+    max_field_size = "1048576",
+    json = "\"never\""
+)]
+#[pyo3(
+    text_signature = "(body, charset, /, *, max_body_size=None, max_parts=1000, max_field_name_length=1024, max_field_size=1048576, json=\"never\")"
+)]
+fn parse_urlencoded_form<'a>(
+    body: &'a [u8],
+    charset: &'a [u8],
+    max_body_size: Option<usize>,
+    max_parts: Option<usize>,
+    max_field_name_length: Option<usize>,
+    max_field_size: Option<usize>,
+    json: &str,
+) -> PyResult<FormData> {
+    let options = ParseOptions {
+        limits: ParserLimits {
+            max_body_size,
+            max_parts,
+            max_field_name_length,
+            max_field_size,
+            ..ParserLimits::DEFAULT
+        },
+        json: json_policy(json)?,
+        ..ParseOptions::default()
+    };
+    Ok(parse_urlencoded_form_with_options(body, charset, &options)?)
+}
+
+#[pyfunction(
+    "*",
+    max_body_size = "None",
+    max_parts = "1000",
+    max_field_name_length = "1024",
+    max_field_size = "1048576",
+    mode = "\"lenient\"",
+    json = "\"never\""
+)]
+#[pyo3(
+    text_signature = "(body, charset, /, *, max_body_size=None, max_parts=1000, max_field_name_length=1024, max_field_size=1048576, mode=\"lenient\", json=\"never\")"
+)]
+#[allow(clippy::too_many_arguments)]
+fn parse_text_plain_form<'a>(
+    body: &'a [u8],
+    charset: &'a [u8],
+    max_body_size: Option<usize>,
+    max_parts: Option<usize>,
+    max_field_name_length: Option<usize>,
+    max_field_size: Option<usize>,
+    mode: &str,
+    json: &str,
+) -> PyResult<FormData> {
+    let options = ParseOptions {
+        limits: ParserLimits {
+            max_body_size,
+            max_parts,
+            max_field_name_length,
+            max_field_size,
+            ..ParserLimits::DEFAULT
+        },
+        mode: parse_mode(mode)?,
+        json: json_policy(json)?,
+        ..ParseOptions::default()
+    };
+    Ok(parse_text_plain_form_with_options(body, charset, &options)?)
+}
+
+/// The `(name, value)` pairs of a mapping or of an iterable of pairs.
+fn pairs(obj: &PyAny) -> PyResult<Vec<(String, &PyAny)>> {
+    let items: &PyAny = match obj.downcast::<PyDict>() {
+        Ok(dict) => dict.items().as_ref(),
+        Err(_) => obj,
+    };
+    items.iter()?.map(|item| item?.extract()).collect()
+}
+
+#[pyfunction(fields = "None", files = "None", "*", boundary = "None")]
+#[pyo3(text_signature = "(fields=None, files=None, *, boundary=None)")]
+fn encode_multipart_form_data<'py>(
+    py: Python<'py>,
+    fields: Option<&PyAny>,
+    files: Option<&PyAny>,
+    boundary: Option<&str>,
+) -> PyResult<(&'py PyBytes, String)> {
+    let mut writer = match boundary {
+        Some(boundary) => MultipartWriter::with_boundary(boundary)?,
+        None => MultipartWriter::new(),
+    };
+
+    // strings are sent as text, anything else as JSON
+    for (name, value) in fields.map(pairs).transpose()?.unwrap_or_default() {
+        match value.extract::<&str>() {
+            Ok(text) => writer.add_text(&name, text),
+            Err(_) => writer.add_json(&name, &depythonize(value)?),
+        };
+    }
+
+    // files are given as `(filename, content)` or `(filename, content, content_type)`, where the
+    // content is bytes, str or the path of a file to read
+    for (name, file) in files.map(pairs).transpose()?.unwrap_or_default() {
+        let (filename, content, content_type): (String, &PyAny, Option<&str>) =
+            match file.extract::<(String, &PyAny)>() {
+                Ok((filename, content)) => (filename, content, None),
+                Err(_) => file.extract()?,
+            };
+
+        if let Ok(content) = content.downcast::<PyBytes>() {
+            writer.add_file(&name, &filename, content_type, content.as_bytes());
+        } else if let Ok(content) = content.extract::<&str>() {
+            writer.add_file(&name, &filename, content_type, content.as_bytes());
+        } else {
+            let path: PathBuf = content.extract()?;
+            writer.add_reader(&name, &filename, content_type, File::open(path)?);
+        }
+    }
+
+    let content_type = writer.content_type();
+    let body = writer.into_vec()?;
+    Ok((PyBytes::new(py, &body), content_type))
 }
 
 #[pymodule]
-fn fast_multipart_parser(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+fn fast_multipart_parser(py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(parse_content_header, m)?)?;
+    m.add_function(wrap_pyfunction!(format_content_disposition, m)?)?;
     m.add_function(wrap_pyfunction!(parse_multipart_form_data, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_multipart, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_urlencoded_form, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_text_plain_form, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_multipart_form_data, m)?)?;
+    m.add_class::<ContentDisposition>()?;
+    m.add_class::<MediaType>()?;
+    m.add_class::<MultiDict>()?;
+    m.add_class::<SpooledFile>()?;
+
+    m.add(
+        "MultipartError",
+        py.get_type::<exceptions::MultipartError>(),
+    )?;
+    m.add(
+        "InvalidBoundaryError",
+        py.get_type::<exceptions::InvalidBoundaryError>(),
+    )?;
+    m.add(
+        "InvalidContentTypeError",
+        py.get_type::<exceptions::InvalidContentTypeError>(),
+    )?;
+    m.add(
+        "MissingClosingDelimiterError",
+        py.get_type::<exceptions::MissingClosingDelimiterError>(),
+    )?;
+    m.add(
+        "MalformedHeaderError",
+        py.get_type::<exceptions::MalformedHeaderError>(),
+    )?;
+    m.add(
+        "MissingContentDispositionError",
+        py.get_type::<exceptions::MissingContentDispositionError>(),
+    )?;
+    m.add(
+        "MissingFieldNameError",
+        py.get_type::<exceptions::MissingFieldNameError>(),
+    )?;
+    m.add(
+        "InvalidDispositionError",
+        py.get_type::<exceptions::InvalidDispositionError>(),
+    )?;
+    m.add(
+        "AmbiguousFormDataError",
+        py.get_type::<exceptions::AmbiguousFormDataError>(),
+    )?;
+    m.add(
+        "LimitExceededError",
+        py.get_type::<exceptions::LimitExceededError>(),
+    )?;
+    m.add(
+        "MalformedEncodingError",
+        py.get_type::<exceptions::MalformedEncodingError>(),
+    )?;
 
     Ok(())
 }
@@ -0,0 +1,173 @@
+use crate::error::MultipartError;
+use std::fmt;
+
+/// The resource limits applied while parsing. A limit of `None` disables the check.
+///
+/// The defaults bound everything that is held in memory per part (headers, field names and
+/// non-file fields) and the number of parts, but leave the overall body and file sizes unbounded,
+/// as those depend on the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserLimits {
+    /// The maximum size of the whole body in bytes.
+    pub max_body_size: Option<usize>,
+    /// The maximum number of parts.
+    pub max_parts: Option<usize>,
+    /// The maximum size of the header block of a single part in bytes.
+    pub max_header_size: Option<usize>,
+    /// The maximum number of headers of a single part.
+    pub max_headers: Option<usize>,
+    /// The maximum length of a field name in bytes.
+    pub max_field_name_length: Option<usize>,
+    /// The maximum size of a part without a filename in bytes.
+    pub max_field_size: Option<usize>,
+    /// The maximum size of a single file in bytes.
+    pub max_file_size: Option<usize>,
+    /// The maximum number of files.
+    pub max_files: Option<usize>,
+}
+
+impl ParserLimits {
+    pub const DEFAULT: ParserLimits = ParserLimits {
+        max_body_size: None,
+        max_parts: Some(1000),
+        max_header_size: Some(16 * 1024),
+        max_headers: Some(32),
+        max_field_name_length: Some(1024),
+        max_field_size: Some(1024 * 1024),
+        max_file_size: None,
+        max_files: Some(1000),
+    };
+
+    /// Limits with every check disabled.
+    pub const UNLIMITED: ParserLimits = ParserLimits {
+        max_body_size: None,
+        max_parts: None,
+        max_header_size: None,
+        max_headers: None,
+        max_field_name_length: None,
+        max_field_size: None,
+        max_file_size: None,
+        max_files: None,
+    };
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Identifies the limit of a [`MultipartError::LimitExceeded`] error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    BodySize,
+    Parts,
+    HeaderSize,
+    Headers,
+    FieldNameLength,
+    FieldSize,
+    FileSize,
+    Files,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // named after the fields of `ParserLimits` (and the python keyword arguments)
+        f.write_str(match self {
+            Limit::BodySize => "max_body_size",
+            Limit::Parts => "max_parts",
+            Limit::HeaderSize => "max_header_size",
+            Limit::Headers => "max_headers",
+            Limit::FieldNameLength => "max_field_name_length",
+            Limit::FieldSize => "max_field_size",
+            Limit::FileSize => "max_file_size",
+            Limit::Files => "max_files",
+        })
+    }
+}
+
+#[inline]
+pub(crate) fn check(
+    limit: Limit,
+    max: Option<usize>,
+    value: usize,
+    offset: usize,
+) -> Result<(), MultipartError> {
+    match max {
+        Some(max) if value > max => Err(MultipartError::LimitExceeded { limit, max, offset }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks `max_body_size` for a body of which `size` bytes are known. The error is reported at the
+/// first byte past the limit, see [`MultipartError::LimitExceeded`].
+pub(crate) fn check_body_size(limits: &ParserLimits, size: usize) -> Result<(), MultipartError> {
+    match limits.max_body_size {
+        Some(max) => check(Limit::BodySize, Some(max), size, max),
+        None => Ok(()),
+    }
+}
+
+/// Enforces the limits that depend on the `Content-Disposition` of a part, which the event level
+/// parser does not interpret.
+#[derive(Debug, Default)]
+pub(crate) struct FieldLimiter {
+    limits: ParserLimits,
+    files: usize,
+    is_file: bool,
+    size: usize,
+}
+
+impl FieldLimiter {
+    pub(crate) fn new(limits: ParserLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Called once the headers of a part are known.
+    pub(crate) fn begin(
+        &mut self,
+        name: Option<&str>,
+        is_file: bool,
+        offset: usize,
+    ) -> Result<(), MultipartError> {
+        self.is_file = is_file;
+        self.size = 0;
+
+        if let Some(name) = name {
+            check(
+                Limit::FieldNameLength,
+                self.limits.max_field_name_length,
+                name.len(),
+                offset,
+            )?;
+        }
+        if is_file {
+            self.files += 1;
+            check(Limit::Files, self.limits.max_files, self.files, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Called for every chunk of the part body.
+    pub(crate) fn data(&mut self, len: usize, offset: usize) -> Result<(), MultipartError> {
+        self.size += len;
+        if self.is_file {
+            check(
+                Limit::FileSize,
+                self.limits.max_file_size,
+                self.size,
+                offset,
+            )
+        } else {
+            check(
+                Limit::FieldSize,
+                self.limits.max_field_size,
+                self.size,
+                offset,
+            )
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::content_header::{parse_content_header, write_parameter};
+use crate::error::MultipartError;
+use pyo3::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed `Content-Type` value such as `application/vnd.api+json; charset=utf-8`.
+///
+/// The type and subtype are lowercased, parameter names are lowercased by the parser.
+#[pyclass(module = "fast_multipart_parser")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    type_: String,
+    subtype: String,
+    params: BTreeMap<String, String>,
+}
+
+impl MediaType {
+    pub fn parse(value: &str) -> Result<Self, MultipartError> {
+        let (essence, params) = parse_content_header(value)?;
+        let (type_, subtype) =
+            essence
+                .split_once('/')
+                .ok_or(MultipartError::MalformedHeaderValue {
+                    reason: "missing subtype",
+                })?;
+        Ok(Self {
+            type_: type_.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params: params.into_iter().collect(),
+        })
+    }
+
+    /// The media type of parts and fields that have no `Content-Type`.
+    pub(crate) fn text_plain() -> Self {
+        Self {
+            type_: String::from("text"),
+            subtype: String::from("plain"),
+            params: BTreeMap::new(),
+        }
+    }
+
+    pub fn type_(&self) -> &str {
+        &self.type_
+    }
+
+    /// The subtype, including the structured syntax suffix if there is one.
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+
+    /// The structured syntax suffix (RFC 6838), e.g. `json` for `application/problem+json`.
+    pub fn suffix(&self) -> Option<&str> {
+        self.subtype.rsplit_once('+').map(|(_, suffix)| suffix)
+    }
+
+    pub fn params(&self) -> &BTreeMap<String, String> {
+        &self.params
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    /// The type and subtype without parameters, e.g. `text/plain`.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.type_, self.subtype)
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = MultipartError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse(value)
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.type_, self.subtype)?;
+        for (name, value) in &self.params {
+            write_parameter(f, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl MediaType {
+    #[new]
+    fn py_new(value: &str) -> PyResult<Self> {
+        Ok(Self::parse(value)?)
+    }
+
+    #[getter(type)]
+    fn py_type(&self) -> &str {
+        self.type_()
+    }
+
+    #[getter(subtype)]
+    fn py_subtype(&self) -> &str {
+        self.subtype()
+    }
+
+    #[getter(suffix)]
+    fn py_suffix(&self) -> Option<&str> {
+        self.suffix()
+    }
+
+    #[getter(params)]
+    fn py_params(&self) -> HashMap<String, String> {
+        self.params.clone().into_iter().collect()
+    }
+
+    #[getter(charset)]
+    fn py_charset(&self) -> Option<&str> {
+        self.charset()
+    }
+
+    #[getter(essence)]
+    fn py_essence(&self) -> String {
+        self.essence()
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<MediaType({:?})>", self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_media_type() {
+        let media_type: MediaType = "Application/Vnd.API+JSON; Charset=UTF-8; q=\"a b\""
+            .parse()
+            .unwrap();
+
+        assert_eq!(media_type.type_(), "application");
+        assert_eq!(media_type.subtype(), "vnd.api+json");
+        assert_eq!(media_type.suffix(), Some("json"));
+        assert_eq!(media_type.essence(), "application/vnd.api+json");
+        assert_eq!(media_type.charset(), Some("UTF-8"));
+        assert_eq!(media_type.param("Q"), Some("a b"));
+
+        let media_type = MediaType::parse("text/plain").unwrap();
+        assert_eq!(media_type.suffix(), None);
+        assert_eq!(media_type.charset(), None);
+        assert!(media_type.params().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_media_type() {
+        for value in ["", "text", "text/", "text/plain; charset"] {
+            assert!(
+                matches!(
+                    MediaType::parse(value),
+                    Err(MultipartError::MalformedHeaderValue { .. })
+                ),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_media_type() {
+        for (value, expected) in [
+            ("TEXT/Plain", "text/plain"),
+            (
+                "multipart/form-data; boundary=\"----x y\"; charset=utf-8",
+                "multipart/form-data; boundary=\"----x y\"; charset=utf-8",
+            ),
+            (
+                "text/plain; title=\"say \\\"hi\\\"\"; b=\"\"",
+                "text/plain; b=\"\"; title=\"say \\\"hi\\\"\"",
+            ),
+            (
+                "text/plain; title*=UTF-8''caf%C3%A9",
+                "text/plain; title*=UTF-8''caf%C3%A9",
+            ),
+        ] {
+            let media_type = MediaType::parse(value).unwrap();
+            assert_eq!(media_type.to_string(), expected);
+            assert_eq!(MediaType::parse(expected).unwrap(), media_type);
+        }
+    }
+}
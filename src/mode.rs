@@ -0,0 +1,99 @@
+use std::fmt;
+
+/// How strictly a body is checked against RFC 7578 and RFC 2046.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Violations of the delimiter and header syntax and parts without a `form-data`
+    /// disposition are rejected.
+    Strict,
+    /// Common deviations are tolerated, each of them is recorded as a [`Repair`].
+    #[default]
+    Lenient,
+}
+
+/// The line breaks accepted around delimiters and in header blocks. Part bodies are never
+/// altered, whatever line breaks they contain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEndings {
+    /// Only CRLF, as required by RFC 2046.
+    #[default]
+    Crlf,
+    /// CRLF or a bare LF, as produced by hand-written clients and test fixtures. Where both are
+    /// possible, as before a delimiter, the CRLF is taken to be the line break.
+    Any,
+}
+
+/// A deviation from the RFCs that was tolerated in [`Mode::Lenient`]. Offsets are byte offsets into
+/// the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repair {
+    /// A delimiter line without the leading `--`, accepted because the boundary was passed
+    /// together with them.
+    DelimiterWithoutDashes { offset: usize },
+    /// The body ended after a delimiter that was not marked as the closing one with `--`.
+    UnterminatedClosingDelimiter { offset: usize },
+    /// Whitespace around a header name was removed.
+    HeaderNameWhitespace { offset: usize },
+    /// A header line contained a bare CR or LF.
+    BareLineBreakInHeader { offset: usize },
+    /// A part had a `Content-Disposition` other than `form-data`.
+    NonFormDataDisposition { offset: usize },
+    /// A parameter value in single quotes, as in `name='file'`, was unquoted.
+    SingleQuotedParameter { offset: usize },
+    /// A backslash in a quoted string that escaped neither `"` nor `\`, as in an unescaped Windows
+    /// path, was kept.
+    BackslashInQuotedString { offset: usize },
+    /// A malformed header parameter was read as well as it could be, or skipped. `reason` is the
+    /// error strict mode reports.
+    MalformedParameter { offset: usize, reason: &'static str },
+}
+
+impl Repair {
+    pub fn offset(&self) -> usize {
+        match *self {
+            Repair::DelimiterWithoutDashes { offset }
+            | Repair::UnterminatedClosingDelimiter { offset }
+            | Repair::HeaderNameWhitespace { offset }
+            | Repair::BareLineBreakInHeader { offset }
+            | Repair::NonFormDataDisposition { offset }
+            | Repair::SingleQuotedParameter { offset }
+            | Repair::BackslashInQuotedString { offset }
+            | Repair::MalformedParameter { offset, .. } => offset,
+        }
+    }
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Repair::DelimiterWithoutDashes { offset } => {
+                write!(f, "delimiter without leading dashes at byte {}", offset)
+            }
+            Repair::UnterminatedClosingDelimiter { offset } => {
+                write!(
+                    f,
+                    "closing delimiter without trailing dashes at byte {}",
+                    offset
+                )
+            }
+            Repair::HeaderNameWhitespace { offset } => {
+                write!(f, "whitespace around header name at byte {}", offset)
+            }
+            Repair::BareLineBreakInHeader { offset } => {
+                write!(f, "bare line break in header at byte {}", offset)
+            }
+            Repair::NonFormDataDisposition { offset } => {
+                write!(f, "part at byte {} is not form-data", offset)
+            }
+            Repair::SingleQuotedParameter { offset } => {
+                write!(f, "single-quoted parameter value at byte {}", offset)
+            }
+            Repair::BackslashInQuotedString { offset } => {
+                write!(f, "backslash kept in quoted string at byte {}", offset)
+            }
+            Repair::MalformedParameter { offset, reason } => {
+                write!(f, "malformed parameter at byte {}: {}", offset, reason)
+            }
+        }
+    }
+}
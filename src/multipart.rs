@@ -1,24 +1,52 @@
+use crate::content_disposition::ContentDisposition;
 use crate::content_header::parse_content_header;
+use crate::encoded_word::decode_encoded_words;
+use crate::error::MultipartError;
+use crate::form_data::FormData;
+use crate::limits::{FieldLimiter, ParserLimits};
+use crate::media_type::MediaType;
+use crate::mode::{LineEndings, Mode};
+use crate::parser::{validate_boundary, Event, Events};
+use crate::spool::{FileContent, Spool};
+use crate::transfer_encoding::{Decoder, TransferEncoding};
 use encoding_rs::{Encoding, UTF_8};
-use lazy_static::lazy_static;
-use percent_encoding::percent_decode;
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use pythonize::pythonize;
-use regex::bytes::Regex;
 use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashMap;
-
-lazy_static! {
-    static ref CONTENT_SEPARATION_REGEX: Regex = Regex::new(r"\r\n\r\n").unwrap();
-}
+use std::ops::Range;
+use std::path::PathBuf;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct UploadFile {
     content_type: String,
+    media_type: Option<MediaType>,
     filename: String,
     headers: HashMap<String, String>,
-    content: Vec<u8>,
+    transfer_encoding: Option<TransferEncoding>,
+    content: FileContent,
+}
+
+impl UploadFile {
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// The parsed `Content-Type`, `None` if it was malformed.
+    pub fn media_type(&self) -> Option<&MediaType> {
+        self.media_type.as_ref()
+    }
+
+    pub fn content(&self) -> &FileContent {
+        &self.content
+    }
+
+    /// The path of the temporary file the content was spooled to, if any.
+    pub fn path(&self) -> Option<&std::path::Path> {
+        self.content.path()
+    }
 }
 
 impl IntoPy<PyObject> for UploadFile {
@@ -29,8 +57,15 @@ impl IntoPy<PyObject> for UploadFile {
             self.content_type.into_py(py),
         )
         .unwrap();
+        dict.set_item::<PyObject, PyObject>("media_type".into_py(py), self.media_type.into_py(py))
+            .unwrap();
         dict.set_item::<PyObject, PyObject>("headers".into_py(py), self.headers.into_py(py))
             .unwrap();
+        dict.set_item::<PyObject, PyObject>(
+            "transfer_encoding".into_py(py),
+            self.transfer_encoding.map(|e| e.as_str()).into_py(py),
+        )
+        .unwrap();
         dict.set_item::<PyObject, PyObject>("filename".into_py(py), self.filename.into_py(py))
             .unwrap();
         dict.set_item::<PyObject, PyObject>("content".into_py(py), self.content.into_py(py))
@@ -42,10 +77,23 @@ impl IntoPy<PyObject> for UploadFile {
 #[derive(Debug, PartialEq, Eq)]
 pub struct JsonField {
     content_type: String,
+    media_type: Option<MediaType>,
     headers: HashMap<String, String>,
+    transfer_encoding: Option<TransferEncoding>,
     content: Value,
 }
 
+impl JsonField {
+    pub fn content(&self) -> &Value {
+        &self.content
+    }
+
+    /// The parsed `Content-Type`, `None` if it was malformed.
+    pub fn media_type(&self) -> Option<&MediaType> {
+        self.media_type.as_ref()
+    }
+}
+
 impl IntoPy<PyObject> for JsonField {
     fn into_py(self, py: Python<'_>) -> PyObject {
         let dict = PyDict::new(py);
@@ -54,8 +102,15 @@ impl IntoPy<PyObject> for JsonField {
             self.content_type.into_py(py),
         )
         .unwrap();
+        dict.set_item::<PyObject, PyObject>("media_type".into_py(py), self.media_type.into_py(py))
+            .unwrap();
         dict.set_item::<PyObject, PyObject>("headers".into_py(py), self.headers.into_py(py))
             .unwrap();
+        dict.set_item::<PyObject, PyObject>(
+            "transfer_encoding".into_py(py),
+            self.transfer_encoding.map(|e| e.as_str()).into_py(py),
+        )
+        .unwrap();
         dict.set_item::<PyObject, PyObject>(
             "content".into_py(py),
             pythonize(py, &self.content).unwrap(),
@@ -68,10 +123,35 @@ impl IntoPy<PyObject> for JsonField {
 #[derive(Debug, PartialEq, Eq)]
 pub struct StringField {
     content_type: String,
+    media_type: Option<MediaType>,
     headers: HashMap<String, String>,
+    transfer_encoding: Option<TransferEncoding>,
+    charset: &'static Encoding,
+    had_replacements: bool,
     content: String,
 }
 
+impl StringField {
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// The parsed `Content-Type`, `None` if it was malformed.
+    pub fn media_type(&self) -> Option<&MediaType> {
+        self.media_type.as_ref()
+    }
+
+    /// The name of the encoding the content was decoded with.
+    pub fn charset(&self) -> &'static str {
+        self.charset.name()
+    }
+
+    /// Whether malformed input was replaced with U+FFFD while decoding.
+    pub fn had_replacements(&self) -> bool {
+        self.had_replacements
+    }
+}
+
 impl IntoPy<PyObject> for StringField {
     fn into_py(self, py: Python<'_>) -> PyObject {
         let dict = PyDict::new(py);
@@ -80,8 +160,22 @@ impl IntoPy<PyObject> for StringField {
             self.content_type.into_py(py),
         )
         .unwrap();
+        dict.set_item::<PyObject, PyObject>("media_type".into_py(py), self.media_type.into_py(py))
+            .unwrap();
         dict.set_item::<PyObject, PyObject>("headers".into_py(py), self.headers.into_py(py))
             .unwrap();
+        dict.set_item::<PyObject, PyObject>(
+            "transfer_encoding".into_py(py),
+            self.transfer_encoding.map(|e| e.as_str()).into_py(py),
+        )
+        .unwrap();
+        dict.set_item::<PyObject, PyObject>("charset".into_py(py), self.charset.name().into_py(py))
+            .unwrap();
+        dict.set_item::<PyObject, PyObject>(
+            "had_replacements".into_py(py),
+            self.had_replacements.into_py(py),
+        )
+        .unwrap();
         dict.set_item::<PyObject, PyObject>("content".into_py(py), self.content.into_py(py))
             .unwrap();
         dict.into_py(py)
@@ -95,6 +189,70 @@ pub enum Field {
     String(StringField),
 }
 
+/// What is known about a field apart from its content.
+pub(crate) struct FieldHead {
+    pub(crate) content_type: String,
+    pub(crate) media_type: Option<MediaType>,
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) transfer_encoding: Option<TransferEncoding>,
+}
+
+impl FieldHead {
+    /// The head of a field that was sent without headers.
+    pub(crate) fn text_plain() -> Self {
+        Self {
+            content_type: String::from("text/plain"),
+            media_type: Some(MediaType::text_plain()),
+            headers: HashMap::new(),
+            transfer_encoding: None,
+        }
+    }
+}
+
+impl Field {
+    /// Builds a field without a filename from its content, which is decoded with `encoding` and
+    /// then parsed as JSON if `parse_json` is set and it is valid JSON, or kept as text otherwise.
+    pub(crate) fn text(
+        head: FieldHead,
+        content: &[u8],
+        encoding: &'static Encoding,
+        parse_json: bool,
+    ) -> Field {
+        let FieldHead {
+            content_type,
+            media_type,
+            headers,
+            transfer_encoding,
+        } = head;
+        match parse_json
+            .then(|| encoding.decode_without_bom_handling_and_without_replacement(content))
+            .flatten()
+            .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+        {
+            Some(json_value) => Field::Json(JsonField {
+                content_type,
+                media_type,
+                headers,
+                transfer_encoding,
+                content: json_value,
+            }),
+            None => {
+                let (decoded, charset, had_replacements) = encoding.decode(content);
+
+                Field::String(StringField {
+                    content_type,
+                    media_type,
+                    headers,
+                    transfer_encoding,
+                    charset,
+                    had_replacements,
+                    content: decoded.into_owned(),
+                })
+            }
+        }
+    }
+}
+
 impl IntoPy<PyObject> for Field {
     fn into_py(self, py: Python<'_>) -> PyObject {
         match self {
@@ -105,156 +263,506 @@ impl IntoPy<PyObject> for Field {
     }
 }
 
-#[inline]
-fn extract_filename(options: HashMap<String, String>) -> Option<String> {
-    match options.get("filename*") {
-        Some(filename_with_asterisk) => {
-            let mut parts = filename_with_asterisk.splitn(3, '\'');
+/// Which fields without a filename are parsed as JSON into a [`Field::Json`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonPolicy {
+    /// Every field is kept as text.
+    Never,
+    /// Fields with an `application/json` or `+json` media type. Content that is not valid JSON is
+    /// kept as text.
+    #[default]
+    ContentType,
+    /// Every field that is valid JSON, whatever its media type.
+    Always,
+}
+
+#[derive(Debug, Default)]
+struct PartBuilder {
+    offset: usize,
+    has_disposition: bool,
+    field_name: Option<String>,
+    filename: Option<String>,
+    content_type: Option<String>,
+    media_type: Option<MediaType>,
+    headers: HashMap<String, String>,
+    transfer_encoding: Option<TransferEncoding>,
+    decoder: Option<Decoder>,
+    content: Vec<u8>,
+    file: Option<Spool>,
+    nested: Option<(String, Range<usize>)>,
+}
+
+impl PartBuilder {
+    fn add_header(
+        &mut self,
+        header_key: &str,
+        header_value: &str,
+        options: &ParseOptions,
+    ) -> Result<(), MultipartError> {
+        let decode = |value: String| {
+            if !options.decode_encoded_words {
+                return value;
+            }
+            match decode_encoded_words(&value) {
+                Cow::Owned(decoded) => decoded,
+                Cow::Borrowed(_) => value,
+            }
+        };
+        // encoded-words are not allowed in these, RFC 2047 section 5
+        let structured = header_key.eq_ignore_ascii_case("content-type")
+            || header_key.eq_ignore_ascii_case("content-transfer-encoding");
 
-            let charset = match parts.next() {
-                None => "UTF-8",
-                Some(charset) => charset,
+        if header_key.eq_ignore_ascii_case("content-type") {
+            self.content_type = Some(header_value.to_owned());
+            self.media_type = MediaType::parse(header_value).ok();
+        }
+
+        if header_key.eq_ignore_ascii_case("content-transfer-encoding") {
+            self.transfer_encoding = TransferEncoding::from_header(header_value);
+        }
+
+        if header_key.eq_ignore_ascii_case("content-disposition") {
+            // what was repaired is recorded by the parser
+            let (disposition, _) = ContentDisposition::parse_with_mode(header_value, options.mode)
+                .map_err(|_| MultipartError::MalformedHeader {
+                    offset: self.offset,
+                })?;
+
+            self.has_disposition = true;
+            self.field_name = disposition.name().map(|name| decode(name.to_owned()));
+            // an RFC 8187 `filename*` is already decoded
+            self.filename = match (disposition.filename_ext(), disposition.filename()) {
+                (Some(filename), _) => Some(filename.to_owned()),
+                (None, filename) => filename.map(|filename| decode(filename.to_owned())),
             };
-            // we have no use for the language component - if its sent.
-            parts.next();
 
-            match parts.next() {
-                None => None,
-                Some(filename) => {
-                    let percent_decoded: Vec<u8> =
-                        percent_decode(filename.as_bytes()).collect::<Vec<u8>>();
+            self.headers.insert(
+                header_key.to_owned(),
+                disposition.disposition_type().to_owned(),
+            );
+        } else if structured {
+            self.headers
+                .insert(header_key.to_owned(), header_value.to_owned());
+        } else {
+            self.headers
+                .insert(header_key.to_owned(), decode(header_value.to_owned()));
+        }
+        Ok(())
+    }
 
-                    let (decoded, ..) = Encoding::for_label(charset.as_bytes())
-                        .unwrap_or(UTF_8)
-                        .decode(percent_decoded.as_slice());
+    /// The boundary of a nested `multipart/mixed` body.
+    fn nested_boundary(&self) -> Option<String> {
+        let media_type = self.media_type.as_ref()?;
+        if media_type.essence() == "multipart/mixed" {
+            media_type.param("boundary").map(str::to_owned)
+        } else {
+            None
+        }
+    }
 
-                    Some(decoded.to_string())
-                }
-            }
+    fn write(
+        &mut self,
+        data: &[u8],
+        limiter: &mut FieldLimiter,
+        offset: usize,
+    ) -> Result<(), MultipartError> {
+        limiter.data(data.len(), offset)?;
+        match &mut self.file {
+            Some(spool) => spool.write(data)?,
+            None => self.content.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    /// The encoding named by the `charset` parameter of the `Content-Type`, if it is known.
+    fn charset(&self) -> Option<&'static Encoding> {
+        Encoding::for_label(self.media_type.as_ref()?.charset()?.as_bytes())
+    }
+
+    /// Checks that the part is a named field and takes its name.
+    fn take_name(&mut self) -> Result<String, MultipartError> {
+        if !self.has_disposition {
+            return Err(MultipartError::MissingContentDisposition {
+                offset: self.offset,
+            });
+        }
+        self.field_name
+            .take()
+            .ok_or(MultipartError::MissingFieldName {
+                offset: self.offset,
+            })
+    }
+
+    fn is_json(&self) -> bool {
+        self.media_type.as_ref().is_some_and(|media_type| {
+            media_type.essence() == "application/json" || media_type.suffix() == Some("json")
+        })
+    }
+
+    /// The `Content-Type` as sent and parsed, `text/plain` if there was none.
+    fn content_type(&mut self) -> (String, Option<MediaType>) {
+        match self.content_type.take() {
+            Some(content_type) => (content_type, self.media_type.take()),
+            None => (String::from("text/plain"), Some(MediaType::text_plain())),
+        }
+    }
+
+    fn build_file(mut self, filename: String) -> Result<Field, MultipartError> {
+        let (content_type, media_type) = self.content_type();
+        Ok(Field::File(UploadFile {
+            content_type,
+            media_type,
+            filename,
+            headers: self.headers,
+            transfer_encoding: self.transfer_encoding,
+            content: match self.file {
+                Some(spool) => spool.finish()?,
+                None => FileContent::Memory(self.content),
+            },
+        }))
+    }
+
+    /// Builds a field without a filename. Text is decoded with the charset of the part, falling
+    /// back to `form_encoding`.
+    fn build_text(mut self, form_encoding: &'static Encoding, json: JsonPolicy) -> Field {
+        let encoding = self.charset().unwrap_or(form_encoding);
+        let parse_json = match json {
+            JsonPolicy::Never => false,
+            JsonPolicy::ContentType => self.is_json(),
+            JsonPolicy::Always => true,
+        };
+        let (content_type, media_type) = self.content_type();
+        let head = FieldHead {
+            content_type,
+            media_type,
+            headers: self.headers,
+            transfer_encoding: self.transfer_encoding,
+        };
+
+        Field::text(head, &self.content, encoding, parse_json)
+    }
+}
+
+/// A part that was read completely. Fields without a filename are only built once every part was
+/// read, as the `_charset_` field that determines their encoding may come last.
+enum Collected {
+    Field(String, Field),
+    Text(String, PartBuilder),
+}
+
+/// Options of [`parse_multipart_form_data_with_options`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub limits: ParserLimits,
+    /// Files larger than this many bytes are written to a temporary file instead of being kept in
+    /// memory. `None` keeps every file in memory.
+    pub spool_threshold: Option<usize>,
+    /// The directory for spooled files, defaults to the system temp directory.
+    pub spool_dir: Option<PathBuf>,
+    /// How deep `multipart/mixed` parts are parsed into the parts they contain (RFC 2388 sends
+    /// several files of one field this way). Deeper parts are kept as a single field.
+    pub max_nesting_depth: usize,
+    pub mode: Mode,
+    pub line_endings: LineEndings,
+    pub json: JsonPolicy,
+    /// Whether RFC 2047 encoded-words (`=?UTF-8?B?...?=`), which some Java and mail-derived clients
+    /// send, are decoded in the `name` and `filename` parameters and in headers other than
+    /// `Content-Type` and `Content-Transfer-Encoding`.
+    pub decode_encoded_words: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            limits: ParserLimits::default(),
+            spool_threshold: None,
+            spool_dir: None,
+            max_nesting_depth: 1,
+            mode: Mode::default(),
+            line_endings: LineEndings::default(),
+            json: JsonPolicy::default(),
+            decode_encoded_words: true,
         }
-        None => options.get("filename").cloned(),
     }
 }
 
+/// Extracts the boundary and the charset, if any, from the value of a `multipart/*` `Content-Type`
+/// header, e.g. `multipart/form-data; boundary="----x"; charset=utf-8`.
+pub fn parse_multipart_content_type(
+    content_type: &str,
+) -> Result<(String, Option<String>), MultipartError> {
+    let (media_type, mut parameters) =
+        parse_content_header(content_type).map_err(|error| match error {
+            MultipartError::MalformedHeaderValue { reason } => {
+                MultipartError::InvalidContentType { reason }
+            }
+            error => error,
+        })?;
+    let is_multipart = media_type
+        .get(..10)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("multipart/"));
+    if !is_multipart {
+        return Err(MultipartError::InvalidContentType {
+            reason: "the media type is not multipart",
+        });
+    }
+
+    let boundary = parameters
+        .remove("boundary")
+        .ok_or(MultipartError::InvalidContentType {
+            reason: "the boundary parameter is missing",
+        })?;
+    validate_boundary(boundary.as_bytes(), Mode::Strict)?;
+
+    Ok((boundary, parameters.remove("charset")))
+}
+
+/// Parses a body given the value of its `Content-Type` header, from which the boundary and the
+/// charset (UTF-8 if there is none) are taken.
+pub fn parse_multipart(
+    body: &[u8],
+    content_type: &str,
+    options: &ParseOptions,
+) -> Result<FormData, MultipartError> {
+    let (boundary, charset) = parse_multipart_content_type(content_type)?;
+    let charset = charset.as_deref().unwrap_or("utf-8");
+    parse_multipart_form_data_with_options(body, boundary.as_bytes(), charset.as_bytes(), options)
+}
+
 pub fn parse_multipart_form_data(
     body: &[u8],
     boundary: &[u8],
     charset: &[u8],
-) -> HashMap<String, Field> {
-    let boundary_re =
-        Regex::new(format!(r"-*({})-*", String::from_utf8(boundary.to_vec()).unwrap()).as_str())
-            .unwrap();
+) -> Result<FormData, MultipartError> {
+    parse_multipart_form_data_with_options(body, boundary, charset, &ParseOptions::default())
+}
 
-    let mut result: HashMap<String, Field> = HashMap::new();
-    let encoding = Encoding::for_label(charset).unwrap_or(UTF_8);
-
-    for form_part in boundary_re.split(body) {
-        let mut filename: Option<String> = None;
-        let mut field_name: Option<String> = None;
-        let mut headers: Vec<(String, String)> = Vec::new();
-        let mut content_type = String::from("text/plain");
-
-        let mut parts = CONTENT_SEPARATION_REGEX.split(form_part);
-
-        match parts.next() {
-            None => continue,
-            Some(headers_bs) => {
-                let headers_value = String::from_utf8(headers_bs.to_vec()).unwrap_or_default();
-
-                if headers_value.contains(':') {
-                    for (header_key, header_value) in headers_value
-                        .split("\r\n")
-                        .filter_map(|part| part.split_once(':'))
-                        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
-                    {
-                        if header_key.to_lowercase() == "content-type" {
-                            content_type = header_value.to_string();
-                        }
+pub fn parse_multipart_form_data_with_options(
+    body: &[u8],
+    boundary: &[u8],
+    charset: &[u8],
+    options: &ParseOptions,
+) -> Result<FormData, MultipartError> {
+    let mut result = FormData::new();
+    let mut collector = Collector {
+        options,
+        limiter: FieldLimiter::new(options.limits),
+        part_count: 0,
+        parts: Vec::new(),
+    };
+    collector.collect(body, 0..body.len(), boundary, None, 0, &mut result)?;
+
+    // the encoding of text fields is taken from their `Content-Type`, then from the `_charset_`
+    // field sent by browsers (HTML form submission), then from the caller. Browsers never encode
+    // forms as UTF-16, so like them `_charset_` maps it to UTF-8.
+    let form_encoding = collector
+        .parts
+        .iter()
+        .find_map(|part| match part {
+            Collected::Text(name, part) if name == "_charset_" => {
+                Encoding::for_label(part.content.trim_ascii()).map(Encoding::output_encoding)
+            }
+            _ => None,
+        })
+        .or_else(|| Encoding::for_label(charset))
+        .unwrap_or(UTF_8);
+
+    for part in collector.parts {
+        match part {
+            Collected::Field(name, field) => result.push(name, field),
+            Collected::Text(name, part) => {
+                result.push(name, part.build_text(form_encoding, options.json))
+            }
+        }
+    }
+
+    Ok(result)
+}
 
-                        if header_key.to_lowercase() == "content-disposition" {
-                            let (value, options) =
-                                parse_content_header(header_value.as_str()).to_owned();
+struct Collector<'o> {
+    options: &'o ParseOptions,
+    limiter: FieldLimiter,
+    /// The parts begun in all bodies so far, nested ones included, which share `max_parts`.
+    part_count: usize,
+    parts: Vec<Collected>,
+}
 
-                            field_name = options.get("name").cloned();
-                            filename = extract_filename(options);
+impl Collector<'_> {
+    /// Collects the parts of `body[range]` into `result`. The parts of a nested body take the name
+    /// of the field that contains them and count towards the same limits. Offsets are those in
+    /// the whole `body`.
+    fn collect(
+        &mut self,
+        body: &[u8],
+        range: Range<usize>,
+        boundary: &[u8],
+        outer_name: Option<&str>,
+        depth: usize,
+        result: &mut FormData,
+    ) -> Result<(), MultipartError> {
+        let options = self.options;
+        let mut part = PartBuilder::default();
+        let mut events = Events::new(&body[range.clone()], boundary)?
+            .with_offset(range.start)
+            .with_limits(options.limits)
+            .with_mode(options.mode)
+            .with_line_endings(options.line_endings)
+            .with_form_data(outer_name.is_none());
+        events.set_parts(self.part_count);
 
-                            headers.push((header_key.to_owned(), value));
-                        } else {
-                            headers.push((header_key.to_owned(), header_value.to_owned()));
-                        }
+        while let Some(event) = events.next() {
+            match event? {
+                Event::PartBegin => {
+                    part = PartBuilder {
+                        offset: events.offset(),
+                        ..PartBuilder::default()
                     }
                 }
-            }
-        }
+                Event::Header { name, value } => part.add_header(&name, &value, options)?,
+                Event::HeadersEnd => {
+                    if let Some(name) = outer_name {
+                        part.has_disposition = true;
+                        part.field_name = Some(name.to_owned());
+                    }
+                    self.limiter.begin(
+                        part.field_name.as_deref(),
+                        part.filename.is_some(),
+                        events.offset(),
+                    )?;
 
-        match field_name {
-            None => continue,
-            Some(name) => match parts.next() {
-                None => continue,
-                Some(mut content_bs) => {
-                    content_bs = match content_bs.strip_suffix(b"\r\n") {
-                        None => content_bs,
-                        Some(stripped) => stripped,
-                    };
-
-                    match filename {
-                        Some(file_name) => {
-                            result.insert(
-                                name.to_string(),
-                                Field::File(UploadFile {
-                                    content_type: content_type.to_owned(),
-                                    filename: file_name.to_string(),
-                                    headers: HashMap::from_iter(headers),
-                                    content: content_bs.to_vec(),
-                                }),
-                            );
+                    if depth < options.max_nesting_depth {
+                        part.nested = part
+                            .nested_boundary()
+                            .map(|boundary| (boundary, events.offset()..events.offset()));
+                    }
+                    if part.filename.is_some() {
+                        part.file = Some(Spool::new(
+                            options.spool_threshold,
+                            options.spool_dir.clone(),
+                        ));
+                    }
+                    part.decoder = part.transfer_encoding.map(Decoder::new);
+                }
+                // the body of a nested part is parsed in place once it is complete
+                Event::Data(_) if part.nested.is_some() => {
+                    if let Some((_, range)) = &mut part.nested {
+                        range.end = events.offset();
+                    }
+                }
+                Event::Data(data) => match part.decoder.as_mut() {
+                    Some(decoder) => {
+                        let mut decoded = Vec::new();
+                        decoder.decode(data, &mut decoded, events.offset())?;
+                        part.write(&decoded, &mut self.limiter, events.offset())?;
+                    }
+                    None => part.write(data, &mut self.limiter, events.offset())?,
+                },
+                Event::PartEnd => {
+                    let part = std::mem::take(&mut part);
+                    match part.nested {
+                        Some((boundary, range)) => {
+                            let name = part.field_name.ok_or(MultipartError::MissingFieldName {
+                                offset: part.offset,
+                            })?;
+                            self.part_count = events.parts();
+                            self.collect(
+                                body,
+                                range,
+                                boundary.as_bytes(),
+                                Some(&name),
+                                depth + 1,
+                                result,
+                            )?;
+                            events.set_parts(self.part_count);
+                        }
+                        None => {
+                            let part = self.finish(part, events.offset())?;
+                            self.parts.push(part);
                         }
-                        None => match serde_json::from_slice::<Value>(content_bs) {
-                            Ok(json_value) => {
-                                result.insert(
-                                    name.to_string(),
-                                    Field::Json(JsonField {
-                                        content_type: content_type.to_owned(),
-                                        headers: HashMap::from_iter(headers),
-                                        content: json_value.to_owned(),
-                                    }),
-                                );
-                            }
-                            Err(_) => {
-                                let (decoded, ..) = encoding.decode(content_bs);
-
-                                result.insert(
-                                    name.to_string(),
-                                    Field::String(StringField {
-                                        content_type: content_type.to_owned(),
-                                        headers: HashMap::from_iter(headers),
-                                        content: decoded.to_string(),
-                                    }),
-                                );
-                            }
-                        },
                     }
                 }
-            },
+                Event::End => break,
+            }
         }
+
+        self.part_count = events.parts();
+        result.add_repairs(events.repairs().iter().copied());
+        Ok(())
     }
 
-    result
+    fn finish(
+        &mut self,
+        mut part: PartBuilder,
+        offset: usize,
+    ) -> Result<Collected, MultipartError> {
+        if let Some(mut decoder) = part.decoder.take() {
+            let mut decoded = Vec::new();
+            decoder.finish(&mut decoded, offset)?;
+            part.write(&decoded, &mut self.limiter, offset)?;
+        }
+
+        let name = part.take_name()?;
+        Ok(match part.filename.take() {
+            Some(filename) => Collected::Field(name, part.build_file(filename)?),
+            None => Collected::Text(name, part),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::limits::Limit;
+    use crate::mode::Repair;
+    use crate::parser::MultipartParser;
+
+    fn comp_as_string(val: &FileContent) -> String {
+        String::from_utf8(val.read().unwrap()).unwrap()
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x00\x10\x00\x00\x00\x10\x08\x06\x00\x00\x00\x1f\xf3\xffa\x00\x00\x00\x0dIDAT\r\n\r\n\x78\x9c\x63\x00\x01\x00\x00\x05\x00\x01\r\n\r\n\x00\x00\x00\x00IEND\xaeB`\x82";
+    const PDF: &[u8] = b"%PDF-1.4\r\n%\xe2\xe3\xcf\xd3\r\n\r\n1 0 obj\r\n<< /Type /Catalog /Pages 2 0 R >>\r\nendobj\r\n\r\nstream\r\n--\r\n\r\n\x00\xff--\r\nendstream\r\n\r\n%%EOF\r\n";
+    const ZIP: &[u8] = b"PK\x03\x04\x14\x00\x00\x00\x08\x00\r\n\r\n\x00\x00--a7f7ac8d4e2e437c877bb7b8d7cc549\r\n\r\n\r\nPK\x01\x02\x14\x00\r\n--\r\n\r\nPK\x05\x06\x00\x00\x00\x00\x01\x00\x01\x00\r\n";
 
-    fn comp_as_string(val: Vec<u8>) -> String {
-        String::from_utf8(val).unwrap()
+    fn file_body(boundary: &[u8], files: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (filename, content_type, content) in files {
+            body.extend_from_slice(b"--");
+            body.extend_from_slice(boundary);
+            body.extend_from_slice(
+                format!(
+                    "\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                    filename, content_type
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--");
+        body.extend_from_slice(boundary);
+        body.extend_from_slice(b"--\r\n");
+        body
+    }
+
+    fn file_contents(result: &FormData) -> Vec<&[u8]> {
+        result
+            .get_all("file")
+            .map(|field| match field {
+                Field::File(UploadFile {
+                    content: FileContent::Memory(content),
+                    ..
+                }) => content.as_slice(),
+                _ => panic!("value should be an UploadFile"),
+            })
+            .collect()
     }
 
     #[test]
     fn test_parse_postman_multipart() {
         let body = b"----------------------------850116600781883365617864\r\nContent-Disposition: form-data; name=\"attributes\"; filename=\"test-attribute_5.tsv\"\r\nContent-Type: text/tab-separated-values\r\n\r\n\"Campaign ID\"\t\"Plate Set ID\"\t\"No\"\n\r\n----------------------------850116600781883365617864\r\nContent-Disposition: form-data; name=\"fasta\"; filename=\"test-sequence_correct_5.fasta\"\r\nContent-Type: application/octet-stream\r\n\r\n>P23G01_IgG1-1411:H:Q10C3:1/1:NID18\r\nCAGGTATTGAA\r\n\r\n----------------------------850116600781883365617864--\r\n";
         let boundary = b"----------------------------850116600781883365617864";
-        let result = parse_multipart_form_data(body, boundary, b"utf-8");
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
 
         let attributes = match result.get("attributes").unwrap() {
             Field::File(field) => field,
@@ -263,7 +771,7 @@ mod tests {
         assert_eq!(attributes.content_type, "text/tab-separated-values");
         assert_eq!(attributes.filename, "test-attribute_5.tsv");
         assert_eq!(
-            comp_as_string(attributes.content.clone()),
+            comp_as_string(&attributes.content),
             "\"Campaign ID\"\t\"Plate Set ID\"\t\"No\"\n"
         );
 
@@ -274,8 +782,8 @@ mod tests {
         assert_eq!(fasta.content_type, "application/octet-stream");
         assert_eq!(fasta.filename, "test-sequence_correct_5.fasta");
         assert_eq!(
-            comp_as_string(fasta.content.clone()),
-            ">P23G01_IgG1-1411:H:Q10C3:1/1:NID18\r\nCAGGTATTGAA"
+            comp_as_string(&fasta.content),
+            ">P23G01_IgG1-1411:H:Q10C3:1/1:NID18\r\nCAGGTATTGAA\r\n"
         );
     }
 
@@ -283,7 +791,7 @@ mod tests {
     fn test_parse_encoded_value() {
         let body = b"--20b303e711c4ab8c443184ac833ab00f\r\nContent-Disposition: form-data; name=\"value\"\r\n\r\nTransf\xc3\xa9rer\r\n--20b303e711c4ab8c443184ac833ab00f--\r\n";
         let boundary = b"20b303e711c4ab8c443184ac833ab00f";
-        let result = parse_multipart_form_data(body, boundary, b"utf-8");
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
 
         let attributes = match result.get("value").unwrap() {
             Field::String(field) => field,
@@ -296,7 +804,7 @@ mod tests {
     fn test_parse_asian_characters() {
         let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"file\"; filename=\"\xe7\x94\xbb\xe5\x83\x8f.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n<file content>\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
         let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
-        let result = parse_multipart_form_data(body, boundary, b"utf-8");
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
 
         let file = match result.get("file").unwrap() {
             Field::File(field) => field,
@@ -305,14 +813,14 @@ mod tests {
 
         assert_eq!(file.content_type, "image/jpeg");
         assert_eq!(file.filename, "画像.jpg");
-        assert_eq!(comp_as_string(file.content.clone()), "<file content>");
+        assert_eq!(comp_as_string(&file.content), "<file content>");
     }
 
     #[test]
     fn test_parse_filename_with_extended_value() {
         let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name='file'; filename*=UTF-8''Na%C3%AFve%20file.jpg\r\nContent-Type: image/jpeg\r\n\r\n<file content>\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
         let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
-        let result = parse_multipart_form_data(body, boundary, b"utf-8");
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
 
         let file = match result.get("file").unwrap() {
             Field::File(field) => field,
@@ -321,14 +829,64 @@ mod tests {
 
         assert_eq!(file.content_type, "image/jpeg");
         assert_eq!(file.filename, "Naïve file.jpg");
-        assert_eq!(comp_as_string(file.content.clone()), "<file content>");
+        assert_eq!(comp_as_string(&file.content), "<file content>");
+        assert_eq!(
+            result.repairs(),
+            [Repair::SingleQuotedParameter { offset: 73 }]
+        );
+    }
+
+    #[test]
+    fn test_parse_continued_filename_and_extended_name() {
+        let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name*=UTF-8''%C3%A9t%C3%A9; filename*0*=UTF-8''Na%C3%AFve%20; filename*1=\"long file.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n<file content>\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
+
+        let file = match result.get("été").unwrap() {
+            Field::File(field) => field,
+            _ => panic!("value should be an UploadFile"),
+        };
+
+        assert_eq!(file.filename, "Naïve long file.jpg");
+        assert_eq!(comp_as_string(&file.content), "<file content>");
+    }
+
+    #[test]
+    fn test_parse_encoded_words() {
+        let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"file\"; filename=\"=?UTF-8?B?55S75YOPLmpwZw==?=\"\r\nContent-Type: image/jpeg\r\nContent-Description: =?ISO-8859-1?Q?caf=E9?=\r\n\r\n<file content>\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
+        let file = match result.get("file").unwrap() {
+            Field::File(field) => field,
+            _ => panic!("value should be an UploadFile"),
+        };
+        assert_eq!(file.filename, "画像.jpg");
+        assert_eq!(file.headers["Content-Description"], "café");
+        assert_eq!(file.headers["Content-Type"], "image/jpeg");
+
+        let options = ParseOptions {
+            decode_encoded_words: false,
+            ..ParseOptions::default()
+        };
+        let result =
+            parse_multipart_form_data_with_options(body, boundary, b"utf-8", &options).unwrap();
+        let file = match result.get("file").unwrap() {
+            Field::File(field) => field,
+            _ => panic!("value should be an UploadFile"),
+        };
+        assert_eq!(file.filename, "=?UTF-8?B?55S75YOPLmpwZw==?=");
+        assert_eq!(
+            file.headers["Content-Description"],
+            "=?ISO-8859-1?Q?caf=E9?="
+        );
     }
 
     #[test]
     fn test_parse_filename_with_extended_value_with_language_tag() {
         let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name='file'; filename*=UTF-8'en'Na%C3%AFve%20file.jpg\r\nContent-Type: image/jpeg\r\n\r\n<file content>\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
         let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
-        let result = parse_multipart_form_data(body, boundary, b"utf-8");
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
 
         let file = match result.get("file").unwrap() {
             Field::File(field) => field,
@@ -337,14 +895,18 @@ mod tests {
 
         assert_eq!(file.content_type, "image/jpeg");
         assert_eq!(file.filename, "Naïve file.jpg");
-        assert_eq!(comp_as_string(file.content.clone()), "<file content>");
+        assert_eq!(comp_as_string(&file.content), "<file content>");
+        assert_eq!(
+            result.repairs(),
+            [Repair::SingleQuotedParameter { offset: 73 }]
+        );
     }
 
     #[test]
     fn test_mixed_files_and_form_data() {
         let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"field0\"\r\n\r\nvalue0\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\nContent-Type: text/plain\r\n\r\n<file content>\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"field1\"\r\n\r\nvalue1\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
         let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
-        let result = parse_multipart_form_data(body, boundary, b"utf-8");
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
 
         let file = match result.get("file").unwrap() {
             Field::File(field) => field,
@@ -353,7 +915,7 @@ mod tests {
 
         assert_eq!(file.content_type, "text/plain");
         assert_eq!(file.filename, "file.txt");
-        assert_eq!(comp_as_string(file.content.clone()), "<file content>");
+        assert_eq!(comp_as_string(&file.content), "<file content>");
 
         let string_field_1 = match result.get("field0").unwrap() {
             Field::String(field) => field,
@@ -371,4 +933,766 @@ mod tests {
         assert_eq!(string_field_2.content_type, "text/plain");
         assert_eq!(string_field_2.content, "value1");
     }
+
+    #[test]
+    fn test_repeated_field_names_are_preserved() {
+        let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"files\"; filename=\"a.txt\"\r\n\r\na\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"tags\"\r\n\r\nred\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"files\"; filename=\"b.txt\"\r\n\r\nb\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"tags\"\r\n\r\nblue\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
+
+        assert_eq!(result.len(), 4);
+        assert_eq!(
+            result.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["files", "tags", "files", "tags"]
+        );
+
+        let filenames: Vec<&str> = result
+            .get_all("files")
+            .map(|field| match field {
+                Field::File(file) => file.filename.as_str(),
+                _ => panic!("value should be an UploadFile"),
+            })
+            .collect();
+        assert_eq!(filenames, ["a.txt", "b.txt"]);
+
+        let tags: Vec<&str> = result
+            .get_all("tags")
+            .map(|field| match field {
+                Field::String(field) => field.content.as_str(),
+                _ => panic!("value should be a String"),
+            })
+            .collect();
+        assert_eq!(tags, ["red", "blue"]);
+
+        match result.get("tags").unwrap() {
+            Field::String(field) => assert_eq!(field.content, "red"),
+            _ => panic!("value should be a String"),
+        }
+    }
+
+    #[test]
+    fn test_part_without_content_disposition() {
+        let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Type: text/plain\r\n\r\nvalue\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+
+        assert!(matches!(
+            parse_multipart_form_data(body, boundary, b"utf-8"),
+            Err(MultipartError::MissingContentDisposition { offset: 36 })
+        ));
+    }
+
+    #[test]
+    fn test_part_without_name() {
+        let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; filename=\"a.txt\"\r\n\r\nvalue\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+
+        assert!(matches!(
+            parse_multipart_form_data(body, boundary, b"utf-8"),
+            Err(MultipartError::MissingFieldName { offset: 36 })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_content_disposition() {
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+        let with_mode = |body: &[u8], mode| {
+            parse_multipart_form_data_with_options(
+                body,
+                boundary,
+                b"utf-8",
+                &ParseOptions {
+                    mode,
+                    ..ParseOptions::default()
+                },
+            )
+        };
+
+        let body = b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data;name=\"a\\\"b\"\r\n\r\nvalue\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n";
+        let result = parse_multipart_form_data(body, boundary, b"utf-8").unwrap();
+        assert!(matches!(result.get("a\"b"), Some(Field::String(_))));
+
+        // lenient mode reads what clients meant, strict mode rejects the header
+        for (body, name, reason) in [
+            (
+                &b"--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\"value\r\n\r\nvalue\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n"[..],
+                "value",
+                "unterminated quoted string",
+            ),
+            (
+                "--a7f7ac8d4e2e437c877bb7b8d7cc549c\r\nContent-Disposition: form-data; name=\\\"你好\\\"\r\n\r\nvalue\r\n--a7f7ac8d4e2e437c877bb7b8d7cc549c--\r\n".as_bytes(),
+                "你好",
+                "invalid parameter value",
+            ),
+        ] {
+            let result = with_mode(body, Mode::Lenient).unwrap();
+            assert!(matches!(result.get(name), Some(Field::String(_))), "{}", name);
+            assert_eq!(
+                result.repairs(),
+                [Repair::MalformedParameter { offset: 73, reason }]
+            );
+
+            assert!(matches!(
+                with_mode(body, Mode::Strict),
+                Err(MultipartError::MalformedHeader { offset: 36 })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_non_utf8_boundary() {
+        assert!(matches!(
+            parse_multipart_form_data(b"", b"\xff\xfe", b"utf-8"),
+            Err(MultipartError::InvalidBoundary { .. })
+        ));
+    }
+
+    #[test]
+    fn test_binary_payloads_are_kept_byte_for_byte() {
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+        let files: [(&str, &str, &[u8]); 3] = [
+            ("image.png", "image/png", PNG),
+            ("document.pdf", "application/pdf", PDF),
+            ("archive.zip", "application/zip", ZIP),
+        ];
+
+        for file in files {
+            let body = file_body(boundary, &[file]);
+            let result = parse_multipart_form_data(&body, boundary, b"utf-8").unwrap();
+            assert_eq!(file_contents(&result), [file.2], "{}", file.0);
+        }
+
+        let body = file_body(boundary, &files);
+        let result = parse_multipart_form_data(&body, boundary, b"utf-8").unwrap();
+        assert_eq!(file_contents(&result), [PNG, PDF, ZIP]);
+    }
+
+    #[test]
+    fn test_content_with_leading_and_trailing_blank_lines() {
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+        let content: &[u8] = b"\r\n\r\nline\r\n\r\n";
+        let body = file_body(boundary, &[("a.txt", "text/plain", content)]);
+        let result = parse_multipart_form_data(&body, boundary, b"utf-8").unwrap();
+
+        assert_eq!(file_contents(&result), [content]);
+    }
+
+    #[test]
+    fn test_binary_payloads_split_across_chunks() {
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+
+        for content in [PNG, PDF, ZIP] {
+            let body = file_body(boundary, &[("file", "application/octet-stream", content)]);
+
+            for chunk_size in [1, 2, 3, 5, 8, 13, 34, 55] {
+                let mut parser = MultipartParser::new(boundary).unwrap();
+                let mut parsed = Vec::new();
+                let mut collect = |parser: &mut MultipartParser| {
+                    while let Some(event) = parser.next_event().unwrap() {
+                        if let Event::Data(data) = event {
+                            parsed.extend_from_slice(data);
+                        }
+                    }
+                };
+
+                for chunk in body.chunks(chunk_size) {
+                    parser.feed(chunk);
+                    collect(&mut parser);
+                }
+                parser.finish();
+                collect(&mut parser);
+
+                assert_eq!(parsed, content, "chunk size {}", chunk_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_field_limits() {
+        let boundary = b"boundary";
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nfile content\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"b.txt\"\r\n\r\nb\r\n--boundary--\r\n";
+        let limited = |limits| {
+            parse_multipart_form_data_with_options(
+                body,
+                boundary,
+                b"utf-8",
+                &ParseOptions {
+                    limits,
+                    ..ParseOptions::default()
+                },
+            )
+        };
+
+        let cases = [
+            (
+                ParserLimits {
+                    max_field_name_length: Some(4),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::FieldNameLength,
+            ),
+            (
+                ParserLimits {
+                    max_field_size: Some(4),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::FieldSize,
+            ),
+            (
+                ParserLimits {
+                    max_file_size: Some(11),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::FileSize,
+            ),
+            (
+                ParserLimits {
+                    max_files: Some(1),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::Files,
+            ),
+        ];
+        for (limits, expected) in cases {
+            assert!(matches!(
+                limited(limits),
+                Err(MultipartError::LimitExceeded { limit, .. }) if limit == expected
+            ));
+        }
+
+        let result = limited(ParserLimits {
+            max_field_name_length: Some(5),
+            max_field_size: Some(5),
+            max_file_size: Some(12),
+            max_files: Some(2),
+            ..ParserLimits::UNLIMITED
+        })
+        .unwrap();
+        assert_eq!(result.len(), 3);
+    }
+
+    #[test]
+    fn test_default_limits_do_not_bound_file_size() {
+        let content = vec![b'x'; 4 * 1024 * 1024];
+        let body = file_body(
+            b"boundary",
+            &[("big.bin", "application/octet-stream", &content)],
+        );
+
+        let result = parse_multipart_form_data(&body, b"boundary", b"utf-8").unwrap();
+        assert_eq!(file_contents(&result), [content.as_slice()]);
+    }
+
+    #[test]
+    fn test_files_above_spool_threshold_are_written_to_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let boundary = b"a7f7ac8d4e2e437c877bb7b8d7cc549c";
+        let body = file_body(
+            boundary,
+            &[
+                ("small.txt", "text/plain", b"small"),
+                ("file.png", "image/png", PNG),
+            ],
+        );
+        let options = ParseOptions {
+            spool_threshold: Some(16),
+            spool_dir: Some(dir.path().to_owned()),
+            ..ParseOptions::default()
+        };
+
+        let result =
+            parse_multipart_form_data_with_options(&body, boundary, b"utf-8", &options).unwrap();
+        let files: Vec<&UploadFile> = result
+            .get_all("file")
+            .map(|field| match field {
+                Field::File(file) => file,
+                _ => panic!("value should be an UploadFile"),
+            })
+            .collect();
+
+        assert_eq!(files[0].content(), &FileContent::Memory(b"small".to_vec()));
+        let path = files[1].path().unwrap().to_owned();
+        assert!(path.starts_with(dir.path()));
+        assert_eq!(std::fs::read(&path).unwrap(), PNG);
+
+        drop(result);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_transfer_encoded_parts_are_decoded() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\nContent-Transfer-Encoding: base64\r\n\r\nAP8NCg0K\r\n--boundary\r\nContent-Disposition: form-data; name=\"text\"\r\nContent-Transfer-Encoding: Quoted-Printable\r\n\r\nTransf=C3=A9rer\r\n--boundary--\r\n";
+        let result = parse_multipart_form_data(body, b"boundary", b"utf-8").unwrap();
+
+        match result.get("file").unwrap() {
+            Field::File(file) => {
+                assert_eq!(file.transfer_encoding, Some(TransferEncoding::Base64));
+                assert_eq!(
+                    file.content,
+                    FileContent::Memory(b"\x00\xff\r\n\r\n".to_vec())
+                );
+            }
+            _ => panic!("value should be an UploadFile"),
+        }
+        match result.get("text").unwrap() {
+            Field::String(text) => {
+                assert_eq!(
+                    text.transfer_encoding,
+                    Some(TransferEncoding::QuotedPrintable)
+                );
+                assert_eq!(text.content, "Transférer");
+            }
+            _ => panic!("value should be a StringField"),
+        }
+    }
+
+    #[test]
+    fn test_malformed_transfer_encoding() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\nContent-Transfer-Encoding: base64\r\n\r\nnot base64!\r\n--boundary--\r\n";
+
+        assert!(matches!(
+            parse_multipart_form_data(body, b"boundary", b"utf-8"),
+            Err(MultipartError::MalformedEncoding {
+                encoding: TransferEncoding::Base64,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_nested_multipart_mixed() {
+        let body = b"--AaB03x\r\nContent-Disposition: form-data; name=\"submit-name\"\r\n\r\nLarry\r\n--AaB03x\r\nContent-Disposition: form-data; name=\"files\"\r\nContent-Type: multipart/mixed; boundary=BbC04y\r\n\r\n--BbC04y\r\nContent-Disposition: file; filename=\"file1.txt\"\r\nContent-Type: text/plain\r\n\r\n... contents of file1.txt ...\r\n--BbC04y\r\nContent-Disposition: file; filename=\"file2.gif\"\r\nContent-Type: image/gif\r\nContent-Transfer-Encoding: base64\r\n\r\nR0lGODlh\r\n--BbC04y--\r\n--AaB03x--\r\n";
+
+        let result = parse_multipart_form_data(body, b"AaB03x", b"utf-8").unwrap();
+        assert_eq!(result.len(), 3);
+        let files: Vec<(&str, &str)> = result
+            .get_all("files")
+            .map(|field| match field {
+                Field::File(file) => (file.filename.as_str(), file.content_type.as_str()),
+                _ => panic!("value should be an UploadFile"),
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [("file1.txt", "text/plain"), ("file2.gif", "image/gif")]
+        );
+        match result.get("files") {
+            Some(Field::File(file)) => assert_eq!(
+                file.content,
+                FileContent::Memory(b"... contents of file1.txt ...".to_vec())
+            ),
+            _ => panic!("value should be an UploadFile"),
+        }
+
+        // the parts of the nested body are not form-data, which strict mode allows
+        let options = ParseOptions {
+            mode: Mode::Strict,
+            ..ParseOptions::default()
+        };
+        let result =
+            parse_multipart_form_data_with_options(body, b"AaB03x", b"utf-8", &options).unwrap();
+        assert_eq!(result.get_all("files").count(), 2);
+
+        let options = ParseOptions {
+            max_nesting_depth: 0,
+            ..ParseOptions::default()
+        };
+        let result =
+            parse_multipart_form_data_with_options(body, b"AaB03x", b"utf-8", &options).unwrap();
+        assert_eq!(result.len(), 2);
+        assert!(matches!(result.get("files"), Some(Field::String(_))));
+    }
+
+    #[test]
+    fn test_nested_parts_count_towards_max_parts() {
+        let body = b"--AaB03x\r\nContent-Disposition: form-data; name=\"files\"\r\nContent-Type: multipart/mixed; boundary=BbC04y\r\n\r\n--BbC04y\r\n\r\nfile1\r\n--BbC04y\r\n\r\nfile2\r\n--BbC04y--\r\n--AaB03x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--AaB03x--\r\n";
+        let position = |needle: &[u8]| {
+            body.windows(needle.len())
+                .position(|window| window == needle)
+                .unwrap()
+        };
+        let with_max_parts = |max_parts| {
+            let options = ParseOptions {
+                limits: ParserLimits {
+                    max_parts: Some(max_parts),
+                    ..ParserLimits::default()
+                },
+                ..ParseOptions::default()
+            };
+            parse_multipart_form_data_with_options(body, b"AaB03x", b"utf-8", &options)
+        };
+
+        assert_eq!(with_max_parts(4).unwrap().len(), 3);
+        // no body has more than two parts, together they have four
+        for (max_parts, needle) in [
+            (3, &b"Content-Disposition: form-data; name=\"a\""[..]),
+            (2, &b"\r\nfile2"[..]),
+        ] {
+            match with_max_parts(max_parts) {
+                Err(MultipartError::LimitExceeded {
+                    limit: Limit::Parts,
+                    offset,
+                    ..
+                }) => assert_eq!(offset, position(needle), "{}", max_parts),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_offsets_are_those_of_the_outer_body() {
+        let body = b"--AaB03x\r\nContent-Disposition: form-data; name=\"files\"\r\nContent-Type: multipart/mixed; boundary=BbC04y\r\n\r\n--BbC04y\r\nContent-Type : text/plain\r\n\r\nfile1\r\n--BbC04y\r\nContent-Transfer-Encoding: base64\r\n\r\n!!!!\r\n--BbC04y--\r\n--AaB03x--\r\n";
+        let position = |needle: &[u8]| {
+            body.windows(needle.len())
+                .position(|window| window == needle)
+                .unwrap()
+        };
+
+        match parse_multipart_form_data(body, b"AaB03x", b"utf-8") {
+            Err(MultipartError::MalformedEncoding { offset, .. }) => {
+                assert_eq!(offset, position(b"\r\n--BbC04y--"))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let body = [
+            &body[..position(b"--BbC04y\r\nContent-Transfer")],
+            b"--BbC04y--\r\n--AaB03x--\r\n",
+        ]
+        .concat();
+        let result = parse_multipart_form_data(&body, b"AaB03x", b"utf-8").unwrap();
+        assert_eq!(
+            result.repairs(),
+            [Repair::HeaderNameWhitespace {
+                offset: position(b"Content-Type : ")
+            }]
+        );
+    }
+
+    #[test]
+    fn test_single_quoted_parameters() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name='file'; filename='a.txt'\r\n\r\nvalue\r\n--boundary--\r\n";
+        let with_mode = |mode| {
+            parse_multipart_form_data_with_options(
+                body,
+                b"boundary",
+                b"utf-8",
+                &ParseOptions {
+                    mode,
+                    ..ParseOptions::default()
+                },
+            )
+        };
+
+        let result = with_mode(Mode::Lenient).unwrap();
+        match result.get("file").unwrap() {
+            Field::File(file) => assert_eq!(file.filename, "a.txt"),
+            _ => panic!("value should be an UploadFile"),
+        }
+        assert_eq!(
+            result.repairs(),
+            [
+                Repair::SingleQuotedParameter { offset: 49 },
+                Repair::SingleQuotedParameter { offset: 66 }
+            ]
+        );
+
+        // the quotes are part of the token in strict mode
+        let result = with_mode(Mode::Strict).unwrap();
+        assert!(!result.contains("file"));
+        match result.get("'file'").unwrap() {
+            Field::File(file) => assert_eq!(file.filename, "'a.txt'"),
+            _ => panic!("value should be an UploadFile"),
+        }
+        assert!(result.repairs().is_empty());
+    }
+
+    #[test]
+    fn test_unescaped_backslashes_in_filename() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"C:\\Users\\x.txt\"\r\n\r\nvalue\r\n--boundary--\r\n";
+        let with_mode = |mode| {
+            parse_multipart_form_data_with_options(
+                body,
+                b"boundary",
+                b"utf-8",
+                &ParseOptions {
+                    mode,
+                    ..ParseOptions::default()
+                },
+            )
+            .unwrap()
+        };
+        let filename = |result: &FormData| match result.get("file") {
+            Some(Field::File(file)) => file.filename.clone(),
+            other => panic!("{:?}", other),
+        };
+
+        let result = with_mode(Mode::Lenient);
+        assert_eq!(filename(&result), "C:\\Users\\x.txt");
+        assert_eq!(
+            result.repairs(),
+            [
+                Repair::BackslashInQuotedString { offset: 69 },
+                Repair::BackslashInQuotedString { offset: 75 }
+            ]
+        );
+
+        // strict mode reads them as quoted pairs, as RFC 9110 does
+        let result = with_mode(Mode::Strict);
+        assert_eq!(filename(&result), "C:Usersx.txt");
+        assert!(result.repairs().is_empty());
+    }
+
+    #[test]
+    fn test_non_form_data_disposition() {
+        let body = b"--boundary\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\nvalue\r\n--boundary--\r\n";
+        let with_mode = |mode| {
+            parse_multipart_form_data_with_options(
+                body,
+                b"boundary",
+                b"utf-8",
+                &ParseOptions {
+                    mode,
+                    ..ParseOptions::default()
+                },
+            )
+        };
+
+        let result = with_mode(Mode::Lenient).unwrap();
+        assert!(result.contains("a"));
+        assert_eq!(
+            result.repairs(),
+            [Repair::NonFormDataDisposition { offset: 12 }]
+        );
+
+        assert!(matches!(
+            with_mode(Mode::Strict),
+            Err(MultipartError::InvalidDisposition { offset: 12 })
+        ));
+    }
+
+    #[test]
+    fn test_bare_lf_line_endings() {
+        let body = b"\n--boundary\nContent-Disposition: form-data; name=\"field\"\n\nvalue\n--boundary\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\n\nline\r\nline\n\n--boundary--\n";
+        let options = ParseOptions {
+            line_endings: LineEndings::Any,
+            ..ParseOptions::default()
+        };
+        let result =
+            parse_multipart_form_data_with_options(body, b"boundary", b"utf-8", &options).unwrap();
+
+        assert_eq!(
+            result.get("field"),
+            Some(&Field::String(StringField {
+                content_type: "text/plain".to_owned(),
+                media_type: Some(MediaType::text_plain()),
+                headers: HashMap::from([(
+                    "Content-Disposition".to_owned(),
+                    "form-data".to_owned()
+                )]),
+                transfer_encoding: None,
+                charset: UTF_8,
+                had_replacements: false,
+                content: "value".to_owned(),
+            }))
+        );
+        match result.get("file") {
+            Some(Field::File(file)) => {
+                assert_eq!(
+                    file.content(),
+                    &FileContent::Memory(b"line\r\nline\n".to_vec())
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert!(matches!(
+            parse_multipart_form_data(body, b"boundary", b"utf-8"),
+            Err(MultipartError::MissingClosingDelimiter { .. })
+        ));
+    }
+
+    #[test]
+    fn test_charset_resolution() {
+        let string_field = |result: &FormData, name: &str| match result.get(name) {
+            Some(Field::String(field)) => (
+                field.content().to_owned(),
+                field.charset(),
+                field.had_replacements(),
+            ),
+            other => panic!("{:?}", other),
+        };
+
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"part\"\r\nContent-Type: text/plain; charset=utf-16le\r\n\r\nh\x00i\x00\r\n--boundary\r\nContent-Disposition: form-data; name=\"form\"\r\n\r\n\xe9t\xe9\r\n--boundary\r\nContent-Disposition: form-data; name=\"unknown\"\r\nContent-Type: text/plain; charset=unknown\r\n\r\ncaf\xe9\r\n--boundary\r\nContent-Disposition: form-data; name=\"_charset_\"\r\n\r\niso-8859-1\r\n--boundary--\r\n";
+        let result = parse_multipart_form_data(body, b"boundary", b"utf-8").unwrap();
+
+        assert_eq!(
+            string_field(&result, "part"),
+            ("hi".to_owned(), "UTF-16LE", false)
+        );
+        match result.get("part") {
+            Some(Field::String(field)) => {
+                let media_type = field.media_type().unwrap();
+                assert_eq!(media_type.essence(), "text/plain");
+                assert_eq!(media_type.charset(), Some("utf-16le"));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            string_field(&result, "form"),
+            ("été".to_owned(), "windows-1252", false)
+        );
+        assert_eq!(
+            string_field(&result, "unknown"),
+            ("café".to_owned(), "windows-1252", false)
+        );
+        assert_eq!(
+            string_field(&result, "_charset_"),
+            ("iso-8859-1".to_owned(), "windows-1252", false)
+        );
+        let names: Vec<&str> = result.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["part", "form", "unknown", "_charset_"]);
+
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"form\"\r\n\r\n\xe9t\xe9\r\n--boundary--\r\n";
+        let result = parse_multipart_form_data(body, b"boundary", b"utf-8").unwrap();
+        assert_eq!(
+            string_field(&result, "form"),
+            ("\u{fffd}t\u{fffd}".to_owned(), "UTF-8", true)
+        );
+
+        // a UTF-16 `_charset_` means UTF-8, as browsers never encode forms as UTF-16
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"form\"\r\n\r\n\xc3\xa9t\xc3\xa9\r\n--boundary\r\nContent-Disposition: form-data; name=\"_charset_\"\r\n\r\nUTF-16LE\r\n--boundary--\r\n";
+        let result = parse_multipart_form_data(body, b"boundary", b"iso-8859-1").unwrap();
+        assert_eq!(
+            string_field(&result, "form"),
+            ("été".to_owned(), "UTF-8", false)
+        );
+    }
+
+    #[test]
+    fn test_json_policy() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"zip\"\r\n\r\n12345\r\n--boundary\r\nContent-Disposition: form-data; name=\"data\"\r\nContent-Type: application/json\r\n\r\n{\"a\": [1, null]}\r\n--boundary\r\nContent-Disposition: form-data; name=\"ld\"\r\nContent-Type: application/ld+json; charset=utf-8\r\n\r\ntrue\r\n--boundary\r\nContent-Disposition: form-data; name=\"invalid\"\r\nContent-Type: application/json\r\n\r\n{\r\n--boundary--\r\n";
+        let kinds = |json| {
+            let options = ParseOptions {
+                json,
+                ..ParseOptions::default()
+            };
+            parse_multipart_form_data_with_options(body, b"boundary", b"utf-8", &options)
+                .unwrap()
+                .iter()
+                .map(|(_, field)| match field {
+                    Field::File(_) => "file",
+                    Field::Json(_) => "json",
+                    Field::String(_) => "string",
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            kinds(JsonPolicy::Never),
+            ["string", "string", "string", "string"]
+        );
+        assert_eq!(
+            kinds(JsonPolicy::ContentType),
+            ["string", "json", "json", "string"]
+        );
+        assert_eq!(
+            kinds(JsonPolicy::Always),
+            ["json", "json", "json", "string"]
+        );
+
+        match parse_multipart_form_data(body, b"boundary", b"utf-8")
+            .unwrap()
+            .get("data")
+        {
+            Some(Field::Json(field)) => {
+                assert_eq!(field.content, serde_json::json!({"a": [1, null]}))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // JSON is decoded with the charset of the part
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"data\"\r\nContent-Type: application/json; charset=utf-16le\r\n\r\n\"\x00\xe9\x00\"\x00\r\n--boundary--\r\n";
+        match parse_multipart_form_data(body, b"boundary", b"utf-8")
+            .unwrap()
+            .get("data")
+        {
+            Some(Field::Json(field)) => assert_eq!(field.content, serde_json::json!("é")),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_multipart_content_type() {
+        for (content_type, boundary, charset) in [
+            ("multipart/form-data; boundary=abc", "abc", None),
+            (
+                "multipart/form-data; boundary=\"----x y\"; charset=iso-8859-1",
+                "----x y",
+                Some("iso-8859-1"),
+            ),
+            ("Multipart/Mixed; Boundary=abc", "abc", None),
+        ] {
+            assert_eq!(
+                parse_multipart_content_type(content_type).unwrap(),
+                (boundary.to_owned(), charset.map(str::to_owned))
+            );
+        }
+
+        for content_type in ["application/json; boundary=abc", "multipart"] {
+            assert!(matches!(
+                parse_multipart_content_type(content_type),
+                Err(MultipartError::InvalidContentType {
+                    reason: "the media type is not multipart"
+                })
+            ));
+        }
+        assert!(matches!(
+            parse_multipart_content_type("multipart/form-data"),
+            Err(MultipartError::InvalidContentType {
+                reason: "the boundary parameter is missing"
+            })
+        ));
+        for (content_type, expected) in [
+            ("", "missing value"),
+            ("multipart/form-data; boundary=a b", "expected a semicolon"),
+            (
+                "multipart/form-data; boundary=\"abc",
+                "unterminated quoted string",
+            ),
+        ] {
+            match parse_multipart_content_type(content_type) {
+                Err(MultipartError::InvalidContentType { reason }) => assert_eq!(reason, expected),
+                other => panic!("{:?}", other),
+            }
+        }
+
+        let too_long = format!("multipart/form-data; boundary={}", "a".repeat(71));
+        for content_type in [
+            "multipart/form-data; boundary=\"\"",
+            "multipart/form-data; boundary=a*b",
+            "multipart/form-data; boundary=\"caf\u{e9}\"",
+            too_long.as_str(),
+        ] {
+            assert!(matches!(
+                parse_multipart_content_type(content_type),
+                Err(MultipartError::InvalidBoundary { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_multipart() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ncaf\xe9\r\n--boundary--\r\n";
+        let result = parse_multipart(
+            body,
+            "multipart/form-data; boundary=\"boundary\"; charset=latin1",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+
+        match result.get("a") {
+            Some(Field::String(field)) => assert_eq!(field.content(), "café"),
+            other => panic!("{:?}", other),
+        }
+    }
 }
@@ -0,0 +1,1267 @@
+use crate::content_disposition::ContentDisposition;
+use crate::error::MultipartError;
+use crate::limits::{check, check_body_size, Limit, ParserLimits};
+use crate::mode::{LineEndings, Mode, Repair};
+use memchr::memmem::{self, Finder};
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// An event emitted while consuming a multipart body.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// A delimiter was read and a new part starts.
+    PartBegin,
+    /// A header line of the current part.
+    Header {
+        name: Cow<'a, str>,
+        value: Cow<'a, str>,
+    },
+    /// The header block of the current part is complete, body data follows.
+    HeadersEnd,
+    /// A chunk of the current part's body.
+    Data(&'a [u8]),
+    /// The body of the current part is complete.
+    PartEnd,
+    /// The closing delimiter was read, no further events follow.
+    End,
+}
+
+#[derive(Debug)]
+pub(crate) enum RawEvent {
+    PartBegin,
+    Header(Range<usize>, Range<usize>),
+    HeadersEnd,
+    Data(Range<usize>),
+    PartEnd,
+    End,
+}
+
+impl RawEvent {
+    pub(crate) fn resolve(self, input: &[u8]) -> Event<'_> {
+        match self {
+            RawEvent::PartBegin => Event::PartBegin,
+            RawEvent::Header(name, value) => Event::Header {
+                name: String::from_utf8_lossy(&input[name]),
+                value: String::from_utf8_lossy(&input[value]),
+            },
+            RawEvent::HeadersEnd => Event::HeadersEnd,
+            RawEvent::Data(range) => Event::Data(&input[range]),
+            RawEvent::PartEnd => Event::PartEnd,
+            RawEvent::End => Event::End,
+        }
+    }
+}
+
+/// The outcome of a single [`Machine::step`]. The `usize` is the number of input bytes consumed.
+#[derive(Debug)]
+pub(crate) enum Step {
+    Event(RawEvent, usize),
+    NeedMore(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Headers,
+    Body,
+    Delimited { last: bool },
+    Epilogue,
+}
+
+enum Scan {
+    Delimiter {
+        start: usize,
+        end: usize,
+        last: bool,
+        bare: bool,
+        unterminated: bool,
+    },
+    NotFound {
+        hold_from: usize,
+    },
+}
+
+enum Trailer {
+    Next(usize),
+    Close(usize),
+    Unterminated(usize),
+    Incomplete,
+    Invalid,
+}
+
+/// Checks a boundary against the grammar of RFC 2046: 1 to 70 digits, letters, `'()+_,-./:=?` or
+/// spaces, not ending with a space. The parser itself is lenient and takes any printable ASCII.
+pub(crate) fn validate_boundary(boundary: &[u8], mode: Mode) -> Result<(), MultipartError> {
+    // a boundary passed together with its leading dashes is tolerated, so allow for them
+    let max_length = match mode {
+        Mode::Lenient if boundary.starts_with(b"--") => 72,
+        _ => 70,
+    };
+    let allowed = |b: u8| match mode {
+        Mode::Strict => b.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&b),
+        Mode::Lenient => b == b' ' || b.is_ascii_graphic(),
+    };
+
+    let reason = if boundary.is_empty() {
+        "the boundary is empty"
+    } else if boundary.len() > max_length {
+        "the boundary is longer than 70 characters"
+    } else if !boundary.iter().all(|&b| allowed(b)) {
+        match mode {
+            Mode::Strict => "the boundary contains characters that RFC 2046 does not allow",
+            Mode::Lenient => "the boundary contains characters that are not printable ASCII",
+        }
+    } else if boundary.ends_with(b" ") {
+        "the boundary ends with a space"
+    } else {
+        return Ok(());
+    };
+
+    Err(MultipartError::InvalidBoundary { reason })
+}
+
+#[inline]
+fn trim(line: &[u8], mut range: Range<usize>) -> Range<usize> {
+    while range.start < range.end && line[range.start].is_ascii_whitespace() {
+        range.start += 1;
+    }
+    while range.end > range.start && line[range.end - 1].is_ascii_whitespace() {
+        range.end -= 1;
+    }
+    range
+}
+
+/// Splits a header line into the ranges of its name and value.
+#[inline]
+fn split_header(line: &[u8]) -> Option<(Range<usize>, Range<usize>)> {
+    match line.iter().position(|&b| b == b':') {
+        Some(colon) if colon > 0 => Some((trim(line, 0..colon), trim(line, colon + 1..line.len()))),
+        _ => None,
+    }
+}
+
+/// Finds the first line break, returning the end of the line and the start of the next one.
+#[inline]
+fn find_line_break(input: &[u8], line_endings: LineEndings) -> Option<(usize, usize)> {
+    match line_endings {
+        LineEndings::Crlf => memmem::find(input, b"\r\n").map(|end| (end, end + 2)),
+        LineEndings::Any => memchr::memchr(b'\n', input).map(|lf| match lf {
+            0 => (0, 1),
+            _ if input[lf - 1] == b'\r' => (lf - 1, lf + 1),
+            _ => (lf, lf + 1),
+        }),
+    }
+}
+
+/// Iterates over the headers of a header block that was already validated by the parser, i.e.
+/// terminated lines without the final empty line.
+pub(crate) fn header_lines(
+    head: &[u8],
+    line_endings: LineEndings,
+) -> impl Iterator<Item = (Cow<'_, str>, Cow<'_, str>)> {
+    let mut rest = head;
+    std::iter::from_fn(move || {
+        let (end, next) = find_line_break(rest, line_endings).unwrap_or((rest.len(), rest.len()));
+        let line = &rest[..end];
+        rest = &rest[next..];
+
+        split_header(line).map(|(name, value)| {
+            (
+                String::from_utf8_lossy(&line[name]),
+                String::from_utf8_lossy(&line[value]),
+            )
+        })
+    })
+}
+
+/// The position independent core of the parser.
+///
+/// It never buffers: every call to `step` receives the unconsumed input and reports how much of it
+/// was consumed, which lets the same machine drive both the buffering [`MultipartParser`] and
+/// zero-copy parsing of a complete body.
+#[derive(Debug)]
+pub(crate) struct Machine {
+    boundary: Vec<u8>,
+    finder: Finder<'static>,
+    state: State,
+    line_start: bool,
+    offset: usize,
+    limits: ParserLimits,
+    parts: usize,
+    header_bytes: usize,
+    headers: usize,
+    mode: Mode,
+    line_endings: LineEndings,
+    /// Whether parts must have a `form-data` disposition, which those of a nested
+    /// `multipart/mixed` body do not.
+    form_data: bool,
+    repairs: Vec<Repair>,
+}
+
+impl Machine {
+    pub(crate) fn new(boundary: &[u8]) -> Result<Self, MultipartError> {
+        validate_boundary(boundary, Mode::Lenient)?;
+
+        Ok(Self {
+            boundary: boundary.to_vec(),
+            finder: Finder::new(boundary).into_owned(),
+            state: State::Preamble,
+            line_start: true,
+            offset: 0,
+            limits: ParserLimits::default(),
+            parts: 0,
+            header_bytes: 0,
+            headers: 0,
+            mode: Mode::default(),
+            line_endings: LineEndings::default(),
+            form_data: true,
+            repairs: Vec::new(),
+        })
+    }
+
+    /// The number of body bytes consumed so far.
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Counts offsets from `offset` instead of 0, for a body that is a slice of a larger one.
+    pub(crate) fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: ParserLimits) {
+        self.limits = limits;
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    pub(crate) fn set_line_endings(&mut self, line_endings: LineEndings) {
+        self.line_endings = line_endings;
+    }
+
+    pub(crate) fn set_form_data(&mut self, form_data: bool) {
+        self.form_data = form_data;
+    }
+
+    /// The number of parts begun so far, checked against `max_parts`.
+    pub(crate) fn parts(&self) -> usize {
+        self.parts
+    }
+
+    pub(crate) fn set_parts(&mut self, parts: usize) {
+        self.parts = parts;
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub(crate) fn line_endings(&self) -> LineEndings {
+        self.line_endings
+    }
+
+    /// The deviations that were tolerated so far.
+    pub(crate) fn repairs(&self) -> &[Repair] {
+        &self.repairs
+    }
+
+    /// Resolves the start of the delimiter line for a boundary found at `index`, and whether it
+    /// lacks the leading dashes. The boundary must be preceded by `--` (or, in lenient mode,
+    /// nothing when the caller passed the boundary with its leading dashes) and a line break or the
+    /// start of the current section. A CRLF is preferred over a bare LF, if those are accepted.
+    fn delimiter_start(&self, input: &[u8], index: usize) -> Option<(usize, bool)> {
+        let dashes = input[..index]
+            .iter()
+            .rev()
+            .take(3)
+            .take_while(|&&b| b == b'-')
+            .count();
+
+        let (line, bare) = match dashes {
+            2 => (index - 2, false),
+            0 if self.mode == Mode::Lenient && self.boundary.starts_with(b"--") => (index, true),
+            _ => return None,
+        };
+
+        if line == 0 && self.line_start {
+            Some((0, bare))
+        } else if line >= 2 && &input[line - 2..line] == b"\r\n" {
+            Some((line - 2, bare))
+        } else if line >= 1 && input[line - 1] == b'\n' && self.line_endings == LineEndings::Any {
+            Some((line - 1, bare))
+        } else {
+            None
+        }
+    }
+
+    /// Resolves the end of a delimiter line for a boundary ending at `index`. The boundary must be
+    /// followed by `--` for the closing delimiter, or by optional transport padding (linear
+    /// whitespace) and a line break.
+    fn delimiter_end(&self, input: &[u8], index: usize, eof: bool) -> Trailer {
+        if input[index..].starts_with(b"--") {
+            return Trailer::Close(index + 2);
+        }
+
+        let padding = input[index..]
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        let end = index + padding;
+
+        match &input[end..] {
+            [b'\r', b'\n', ..] => Trailer::Next(end + 2),
+            [b'\n', ..] if self.line_endings == LineEndings::Any => Trailer::Next(end + 1),
+            [b'-'] if padding == 0 && !eof => Trailer::Incomplete,
+            [] | [b'\r'] if !eof => Trailer::Incomplete,
+            [] if self.mode == Mode::Lenient => Trailer::Unterminated(end),
+            _ => Trailer::Invalid,
+        }
+    }
+
+    fn scan(&self, input: &[u8], eof: bool) -> Scan {
+        let mut from = 0;
+
+        while let Some(index) = self.finder.find(&input[from..]).map(|i| i + from) {
+            if let Some((start, bare)) = self.delimiter_start(input, index) {
+                let delimiter = |end, last, unterminated| Scan::Delimiter {
+                    start,
+                    end,
+                    last,
+                    bare,
+                    unterminated,
+                };
+                match self.delimiter_end(input, index + self.boundary.len(), eof) {
+                    Trailer::Next(end) => return delimiter(end, false, false),
+                    Trailer::Close(end) => return delimiter(end, true, false),
+                    Trailer::Unterminated(end) => return delimiter(end, true, true),
+                    Trailer::Incomplete => return Scan::NotFound { hold_from: start },
+                    Trailer::Invalid => {}
+                }
+            }
+            from = index + 1;
+        }
+
+        Scan::NotFound {
+            hold_from: self.hold_from(input, eof),
+        }
+    }
+
+    /// Checks whether `line` could be the beginning of a delimiter line that is not complete yet.
+    fn is_delimiter_prefix(&self, line: &[u8]) -> bool {
+        let dashes = line.len().min(2);
+        let with_dashes =
+            line[..dashes].iter().all(|&b| b == b'-') && self.boundary.starts_with(&line[dashes..]);
+
+        with_dashes || (self.boundary.starts_with(b"--") && self.boundary.starts_with(line))
+    }
+
+    /// Returns the position from which the tail of `input` might be the beginning of a delimiter
+    /// that is split across chunks, and therefore must not be consumed yet.
+    fn hold_from(&self, input: &[u8], eof: bool) -> usize {
+        if eof {
+            return input.len();
+        }
+        if self.line_start && self.is_delimiter_prefix(input) {
+            return 0;
+        }
+
+        let window = input.len().saturating_sub(self.boundary.len() + 4);
+        (window..input.len())
+            .find(|&i| match &input[i..] {
+                [b'\r'] | [b'\r', b'\n'] => true,
+                [b'\r', b'\n', line @ ..] => self.is_delimiter_prefix(line),
+                [b'\n', line @ ..] if self.line_endings == LineEndings::Any => {
+                    self.is_delimiter_prefix(line)
+                }
+                _ => false,
+            })
+            .unwrap_or(input.len())
+    }
+
+    pub(crate) fn step(&mut self, input: &[u8], eof: bool) -> Result<Step, MultipartError> {
+        check_body_size(&self.limits, self.offset + input.len())?;
+
+        let step = self.advance(input, eof)?;
+        self.offset += match step {
+            Step::Event(_, consumed) | Step::NeedMore(consumed) => consumed,
+        };
+        Ok(step)
+    }
+
+    fn advance(&mut self, input: &[u8], eof: bool) -> Result<Step, MultipartError> {
+        Ok(match self.state {
+            State::Preamble => match self.scan(input, eof) {
+                Scan::Delimiter {
+                    start,
+                    end,
+                    last,
+                    bare,
+                    unterminated,
+                } => {
+                    self.record_delimiter(start, end, bare, unterminated);
+                    if last {
+                        self.state = State::Epilogue;
+                        Step::Event(RawEvent::End, end)
+                    } else {
+                        self.begin_part(end)?
+                    }
+                }
+                Scan::NotFound { hold_from } if eof => {
+                    return Err(MultipartError::MissingClosingDelimiter {
+                        offset: self.offset + hold_from,
+                    })
+                }
+                Scan::NotFound { hold_from } => {
+                    if hold_from > 0 {
+                        self.line_start = false;
+                    }
+                    Step::NeedMore(hold_from)
+                }
+            },
+            State::Delimited { last: false } => self.begin_part(0)?,
+            State::Delimited { last: true } => {
+                self.state = State::Epilogue;
+                Step::Event(RawEvent::End, 0)
+            }
+            State::Headers => self.header_line(input, eof)?,
+            State::Body => match self.scan(input, eof) {
+                Scan::Delimiter { start, .. } if start > 0 => {
+                    self.line_start = false;
+                    Step::Event(RawEvent::Data(0..start), start)
+                }
+                Scan::Delimiter {
+                    start,
+                    end,
+                    last,
+                    bare,
+                    unterminated,
+                } => {
+                    self.record_delimiter(start, end, bare, unterminated);
+                    self.state = State::Delimited { last };
+                    Step::Event(RawEvent::PartEnd, end)
+                }
+                Scan::NotFound { hold_from } if hold_from > 0 => {
+                    self.line_start = false;
+                    Step::Event(RawEvent::Data(0..hold_from), hold_from)
+                }
+                Scan::NotFound { .. } if eof => {
+                    return Err(MultipartError::MissingClosingDelimiter {
+                        offset: self.offset,
+                    })
+                }
+                Scan::NotFound { .. } => Step::NeedMore(0),
+            },
+            State::Epilogue => Step::NeedMore(input.len()),
+        })
+    }
+
+    fn record_delimiter(&mut self, start: usize, end: usize, bare: bool, unterminated: bool) {
+        // all delimiters lack the dashes in this case, so only the first one is reported
+        let reported = |repair: &Repair| matches!(repair, Repair::DelimiterWithoutDashes { .. });
+        if bare && !self.repairs.iter().any(reported) {
+            self.repairs.push(Repair::DelimiterWithoutDashes {
+                offset: self.offset + start,
+            });
+        }
+        if unterminated {
+            self.repairs.push(Repair::UnterminatedClosingDelimiter {
+                offset: self.offset + end,
+            });
+        }
+    }
+
+    /// Checks the syntax of a header line beyond what is needed to split it, see [`Mode`].
+    fn check_header_line(
+        &mut self,
+        line: &[u8],
+        name: &Range<usize>,
+    ) -> Result<(), MultipartError> {
+        let padded_name = name.start > 0 || line[name.end] != b':';
+        let bare_line_break = memchr::memchr2(b'\r', b'\n', line).is_some();
+
+        for (violated, repair) in [
+            (
+                padded_name,
+                Repair::HeaderNameWhitespace {
+                    offset: self.offset,
+                },
+            ),
+            (
+                bare_line_break,
+                Repair::BareLineBreakInHeader {
+                    offset: self.offset,
+                },
+            ),
+        ] {
+            match self.mode {
+                _ if !violated => {}
+                Mode::Strict => {
+                    return Err(MultipartError::MalformedHeader {
+                        offset: self.offset,
+                    })
+                }
+                Mode::Lenient => self.repairs.push(repair),
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses a `Content-Disposition` at `offset`, recording what was repaired, and checks that it
+    /// is `form-data` unless parts of a nested body are parsed, see [`Mode`]. In lenient mode, a
+    /// malformed one is left to the consumer of the part.
+    fn check_disposition(&mut self, value: &[u8], offset: usize) -> Result<(), MultipartError> {
+        let value = String::from_utf8_lossy(value);
+        let disposition = match ContentDisposition::parse_at(&value, self.mode, offset) {
+            Ok((disposition, repairs)) => {
+                self.repairs.extend(repairs);
+                disposition
+            }
+            Err(_) if self.mode == Mode::Lenient => return Ok(()),
+            Err(_) => {
+                return Err(MultipartError::MalformedHeader {
+                    offset: self.offset,
+                })
+            }
+        };
+        match self.mode {
+            _ if !self.form_data || disposition.disposition_type() == "form-data" => {}
+            Mode::Strict => {
+                return Err(MultipartError::InvalidDisposition {
+                    offset: self.offset,
+                })
+            }
+            Mode::Lenient => self.repairs.push(Repair::NonFormDataDisposition {
+                offset: self.offset,
+            }),
+        }
+        Ok(())
+    }
+
+    fn begin_part(&mut self, consumed: usize) -> Result<Step, MultipartError> {
+        self.parts += 1;
+        check(Limit::Parts, self.limits.max_parts, self.parts, self.offset)?;
+
+        self.state = State::Headers;
+        self.header_bytes = 0;
+        self.headers = 0;
+        Ok(Step::Event(RawEvent::PartBegin, consumed))
+    }
+
+    fn header_line(&mut self, input: &[u8], eof: bool) -> Result<Step, MultipartError> {
+        let line = find_line_break(input, self.line_endings);
+        // an unterminated line counts as well, so that it can not grow without bounds
+        let line_bytes = line.map_or(input.len(), |(_, next)| next);
+        check(
+            Limit::HeaderSize,
+            self.limits.max_header_size,
+            self.header_bytes + line_bytes,
+            self.offset,
+        )?;
+
+        match line {
+            Some((0, next)) => {
+                self.state = State::Body;
+                self.line_start = true;
+                Ok(Step::Event(RawEvent::HeadersEnd, next))
+            }
+            Some((end, _)) => match split_header(&input[..end]) {
+                Some((name, value)) => {
+                    self.check_header_line(&input[..end], &name)?;
+                    if input[name.clone()].eq_ignore_ascii_case(b"content-disposition") {
+                        self.check_disposition(&input[value.clone()], self.offset + value.start)?;
+                    }
+                    self.headers += 1;
+                    check(
+                        Limit::Headers,
+                        self.limits.max_headers,
+                        self.headers,
+                        self.offset,
+                    )?;
+
+                    self.header_bytes += line_bytes;
+                    Ok(Step::Event(RawEvent::Header(name, value), line_bytes))
+                }
+                _ => Err(MultipartError::MalformedHeader {
+                    offset: self.offset,
+                }),
+            },
+            None if eof => Err(MultipartError::MissingClosingDelimiter {
+                offset: self.offset + input.len(),
+            }),
+            None => Ok(Step::NeedMore(0)),
+        }
+    }
+}
+
+/// Implements the options of a front end of [`Machine`], reached through the given fields, e.g.
+/// `machine_options!(scanner.machine)`. With `; without limits`, `with_limits` is left to front
+/// ends that enforce more limits than the machine.
+macro_rules! machine_options {
+    ($($field:ident).+) => {
+        /// Replaces the default [`ParserLimits`]. Only the limits on the body size, the number of
+        /// parts and the part headers apply at this level.
+        pub fn with_limits(mut self, limits: ParserLimits) -> Self {
+            self.$($field).+.set_limits(limits);
+            self
+        }
+
+        $crate::parser::machine_options!($($field).+; without limits);
+    };
+    ($($field:ident).+; without limits) => {
+        /// Replaces the default [`Mode::Lenient`].
+        pub fn with_mode(mut self, mode: Mode) -> Self {
+            self.$($field).+.set_mode(mode);
+            self
+        }
+
+        /// Replaces the default [`LineEndings::Crlf`].
+        pub fn with_line_endings(mut self, line_endings: LineEndings) -> Self {
+            self.$($field).+.set_line_endings(line_endings);
+            self
+        }
+
+        /// The deviations that were tolerated so far in [`Mode::Lenient`].
+        pub fn repairs(&self) -> &[Repair] {
+            self.$($field).+.repairs()
+        }
+    };
+}
+
+pub(crate) use machine_options;
+
+/// An incremental multipart parser that does not perform any IO.
+///
+/// Body chunks of arbitrary size are passed to [`MultipartParser::feed`], and the resulting
+/// events are pulled with [`MultipartParser::next_event`] until it returns `None`, at which point
+/// more input is required. Once the whole body has been fed, [`MultipartParser::finish`] flushes
+/// any data that was held back because it might have been the start of a delimiter.
+///
+/// ```
+/// use fast_multipart_parser::{Event, MultipartParser};
+///
+/// let chunks: [&[u8]; 3] = [
+///     b"--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nval",
+///     b"ue\r\n--boun",
+///     b"dary--\r\n",
+/// ];
+/// let mut parser = MultipartParser::new(b"boundary")?;
+/// let mut content = Vec::new();
+///
+/// for chunk in chunks {
+///     parser.feed(chunk);
+///     while let Some(event) = parser.next_event()? {
+///         if let Event::Data(data) = event {
+///             content.extend_from_slice(data);
+///         }
+///     }
+/// }
+/// parser.finish();
+/// while parser.next_event()?.is_some() {}
+///
+/// assert_eq!(content, b"value");
+/// # Ok::<(), fast_multipart_parser::MultipartError>(())
+/// ```
+#[derive(Debug)]
+pub struct MultipartParser {
+    machine: Machine,
+    buffer: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &[u8]) -> Result<Self, MultipartError> {
+        Ok(Self {
+            machine: Machine::new(boundary)?,
+            buffer: Vec::new(),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    machine_options!(machine);
+
+    /// Appends a chunk of the body to the parser.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        if self.position > 0 {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Signals that the whole body has been fed.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// The number of body bytes consumed so far.
+    pub fn offset(&self) -> usize {
+        self.machine.offset()
+    }
+
+    /// Returns the next event, or `None` if more input is needed (or the body was fully parsed).
+    pub fn next_event(&mut self) -> Result<Option<Event<'_>>, MultipartError> {
+        let start = self.position;
+        match self.machine.step(&self.buffer[start..], self.finished)? {
+            Step::Event(event, consumed) => {
+                self.position += consumed;
+                Ok(Some(event.resolve(&self.buffer[start..])))
+            }
+            Step::NeedMore(consumed) => {
+                self.position += consumed;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Iterates over the events of a complete body without copying it.
+#[derive(Debug)]
+pub(crate) struct Events<'a> {
+    machine: Machine,
+    input: &'a [u8],
+    done: bool,
+}
+
+impl<'a> Events<'a> {
+    pub(crate) fn new(body: &'a [u8], boundary: &[u8]) -> Result<Self, MultipartError> {
+        Ok(Self {
+            machine: Machine::new(boundary)?,
+            input: body,
+            done: false,
+        })
+    }
+
+    /// Reports offsets, in events and errors alike, relative to a body that starts `offset` bytes
+    /// before `body`.
+    pub(crate) fn with_offset(mut self, offset: usize) -> Self {
+        self.machine.set_offset(offset);
+        self
+    }
+
+    machine_options!(machine);
+
+    /// Whether parts must have a `form-data` disposition, `true` by default.
+    pub(crate) fn with_form_data(mut self, form_data: bool) -> Self {
+        self.machine.set_form_data(form_data);
+        self
+    }
+
+    /// The number of body bytes consumed so far.
+    pub(crate) fn offset(&self) -> usize {
+        self.machine.offset()
+    }
+
+    pub(crate) fn parts(&self) -> usize {
+        self.machine.parts()
+    }
+
+    /// Counts `max_parts` from `parts` on, for parts shared with other bodies.
+    pub(crate) fn set_parts(&mut self, parts: usize) {
+        self.machine.set_parts(parts);
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<Event<'a>, MultipartError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.machine.step(self.input, true) {
+            Ok(Step::Event(event, consumed)) => {
+                let input = self.input;
+                self.input = &input[consumed..];
+                Some(Ok(event.resolve(input)))
+            }
+            Ok(Step::NeedMore(_)) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\nContent-Type: text/plain\r\n\r\nline 1\r\n\r\nline 2 --boundar\r\n--boundary--\r\nepilogue";
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Owned {
+        PartBegin,
+        Header(String, String),
+        HeadersEnd,
+        Data(Vec<u8>),
+        PartEnd,
+        End,
+    }
+
+    fn expected() -> Vec<Owned> {
+        vec![
+            Owned::PartBegin,
+            Owned::Header(
+                "Content-Disposition".into(),
+                "form-data; name=\"field\"".into(),
+            ),
+            Owned::HeadersEnd,
+            Owned::Data(b"value".to_vec()),
+            Owned::PartEnd,
+            Owned::PartBegin,
+            Owned::Header(
+                "Content-Disposition".into(),
+                "form-data; name=\"file\"; filename=\"file.txt\"".into(),
+            ),
+            Owned::Header("Content-Type".into(), "text/plain".into()),
+            Owned::HeadersEnd,
+            Owned::Data(b"line 1\r\n\r\nline 2 --boundar".to_vec()),
+            Owned::PartEnd,
+            Owned::End,
+        ]
+    }
+
+    /// Collects events, merging consecutive data chunks so results are comparable across chunk sizes.
+    fn collect(events: &mut Vec<Owned>, event: Event) {
+        match event {
+            Event::Data(data) => match events.last_mut() {
+                Some(Owned::Data(existing)) => existing.extend_from_slice(data),
+                _ => events.push(Owned::Data(data.to_vec())),
+            },
+            Event::PartBegin => events.push(Owned::PartBegin),
+            Event::Header { name, value } => {
+                events.push(Owned::Header(name.into_owned(), value.into_owned()))
+            }
+            Event::HeadersEnd => events.push(Owned::HeadersEnd),
+            Event::PartEnd => events.push(Owned::PartEnd),
+            Event::End => events.push(Owned::End),
+        }
+    }
+
+    fn try_parse_chunked(
+        body: &[u8],
+        boundary: &[u8],
+        chunk_size: usize,
+    ) -> Result<Vec<Owned>, MultipartError> {
+        try_parse_limited(body, boundary, chunk_size, ParserLimits::default())
+    }
+
+    fn try_parse_limited(
+        body: &[u8],
+        boundary: &[u8],
+        chunk_size: usize,
+        limits: ParserLimits,
+    ) -> Result<Vec<Owned>, MultipartError> {
+        let mut parser = MultipartParser::new(boundary)?.with_limits(limits);
+        let mut events = Vec::new();
+
+        for chunk in body.chunks(chunk_size) {
+            parser.feed(chunk);
+            while let Some(event) = parser.next_event()? {
+                collect(&mut events, event);
+            }
+        }
+        parser.finish();
+        while let Some(event) = parser.next_event()? {
+            collect(&mut events, event);
+        }
+
+        Ok(events)
+    }
+
+    fn parse_chunked(body: &[u8], boundary: &[u8], chunk_size: usize) -> Vec<Owned> {
+        try_parse_chunked(body, boundary, chunk_size).unwrap()
+    }
+
+    #[test]
+    fn test_events_complete_body() {
+        let mut events = Vec::new();
+        for event in Events::new(BODY, b"boundary").unwrap() {
+            collect(&mut events, event.unwrap());
+        }
+        assert_eq!(events, expected());
+    }
+
+    #[test]
+    fn test_events_every_chunk_size() {
+        for chunk_size in 1..=BODY.len() {
+            assert_eq!(
+                parse_chunked(BODY, b"boundary", chunk_size),
+                expected(),
+                "chunk size {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn test_data_is_not_copied_for_complete_body() {
+        let data = Events::new(BODY, b"boundary")
+            .unwrap()
+            .find_map(|event| match event.unwrap() {
+                Event::Data(data) => Some(data),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(data, b"value");
+        assert!(BODY.as_ptr_range().contains(&data.as_ptr()));
+    }
+
+    #[test]
+    fn test_empty_part_body() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"empty\"\r\n\r\n\r\n--boundary--\r\n";
+        assert_eq!(
+            parse_chunked(body, b"boundary", 3),
+            vec![
+                Owned::PartBegin,
+                Owned::Header(
+                    "Content-Disposition".into(),
+                    "form-data; name=\"empty\"".into()
+                ),
+                Owned::HeadersEnd,
+                Owned::PartEnd,
+                Owned::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_boundary_with_leading_dashes_passed_as_delimiter() {
+        let body =
+            b"----abc\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n----abc--\r\n";
+        let events = parse_chunked(body, b"----abc", 5);
+        assert!(events.contains(&Owned::Data(b"1".to_vec())));
+        assert_eq!(events.last(), Some(&Owned::End));
+    }
+
+    #[test]
+    fn test_invalid_boundary() {
+        for boundary in [
+            &b""[..],
+            b"bound\r\nary",
+            b"bound\xffary",
+            b"boundary ",
+            &[b'a'; 71],
+        ] {
+            assert!(matches!(
+                MultipartParser::new(boundary),
+                Err(MultipartError::InvalidBoundary { .. })
+            ));
+        }
+
+        // only RFC 2046 boundaries are valid in strict mode, and the dashes count towards the length
+        for (boundary, valid) in [
+            (&b"'()+_,-./:=? x"[..], true),
+            (b"a{b}", false),
+            (&[b'-'; 72], false),
+        ] {
+            assert!(validate_boundary(boundary, Mode::Lenient).is_ok());
+            assert_eq!(
+                validate_boundary(boundary, Mode::Strict).is_ok(),
+                valid,
+                "{:?}",
+                boundary
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_closing_delimiter() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue";
+        for chunk_size in [1, body.len()] {
+            assert!(matches!(
+                try_parse_chunked(body, b"boundary", chunk_size),
+                Err(MultipartError::MissingClosingDelimiter { offset }) if offset == body.len()
+            ));
+        }
+
+        assert!(matches!(
+            try_parse_chunked(b"no delimiter", b"boundary", 4),
+            Err(MultipartError::MissingClosingDelimiter { offset: 12 })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_header() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\nnot a header\r\n\r\nvalue\r\n--boundary--";
+        assert!(matches!(
+            try_parse_chunked(body, b"boundary", 7),
+            Err(MultipartError::MalformedHeader { offset: 54 })
+        ));
+    }
+
+    #[test]
+    fn test_boundary_with_regex_special_characters() {
+        for boundary in [&b"a+b"[..], b"a.b", b"(ab)", b"ab?", b"'()+_,-./:=? x"] {
+            let mut body = b"--".to_vec();
+            body.extend_from_slice(boundary);
+            body.extend_from_slice(
+                b"\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--",
+            );
+            body.extend_from_slice(boundary);
+            body.extend_from_slice(b"--\r\n");
+
+            let events = parse_chunked(&body, boundary, 3);
+            assert!(events.contains(&Owned::Data(b"value".to_vec())));
+            assert_eq!(events.last(), Some(&Owned::End));
+        }
+    }
+
+    #[test]
+    fn test_boundary_inside_content_is_not_a_delimiter() {
+        let content = b"boundary\r\nx--boundary\r\n--boundary-x\r\n--boundaryx\r\n---boundary\r\n";
+        let mut body = b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n".to_vec();
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n--boundary--");
+
+        for chunk_size in 1..=body.len() {
+            let events = parse_chunked(&body, b"boundary", chunk_size);
+            assert!(events.contains(&Owned::Data(content.to_vec())));
+        }
+    }
+
+    #[test]
+    fn test_delimiter_with_transport_padding() {
+        let body = b"--boundary \t\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--boundary\t\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\n\r\n--boundary--";
+
+        for chunk_size in 1..=body.len() {
+            let events = parse_chunked(body, b"boundary", chunk_size);
+            assert_eq!(
+                events.iter().filter(|e| **e == Owned::PartEnd).count(),
+                2,
+                "chunk size {}",
+                chunk_size
+            );
+            assert!(events.contains(&Owned::Data(b"value".to_vec())));
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let cases = [
+            (
+                ParserLimits {
+                    max_body_size: Some(BODY.len() - 1),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::BodySize,
+            ),
+            (
+                ParserLimits {
+                    max_parts: Some(1),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::Parts,
+            ),
+            (
+                ParserLimits {
+                    max_header_size: Some(60),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::HeaderSize,
+            ),
+            (
+                ParserLimits {
+                    max_headers: Some(1),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::Headers,
+            ),
+        ];
+
+        for (limits, expected) in cases {
+            for chunk_size in [1, 7, BODY.len()] {
+                assert!(matches!(
+                    try_parse_limited(BODY, b"boundary", chunk_size, limits),
+                    Err(MultipartError::LimitExceeded { limit, .. }) if limit == expected
+                ));
+            }
+        }
+
+        let limits = ParserLimits {
+            max_body_size: Some(20),
+            ..ParserLimits::UNLIMITED
+        };
+        for chunk_size in [1, 7, BODY.len()] {
+            assert!(matches!(
+                try_parse_limited(BODY, b"boundary", chunk_size, limits),
+                Err(MultipartError::LimitExceeded {
+                    limit: Limit::BodySize,
+                    offset: 20,
+                    ..
+                })
+            ));
+        }
+
+        let limits = ParserLimits {
+            max_body_size: Some(BODY.len()),
+            max_parts: Some(2),
+            max_header_size: Some(100),
+            max_headers: Some(2),
+            ..ParserLimits::UNLIMITED
+        };
+        assert_eq!(
+            try_parse_limited(BODY, b"boundary", 7, limits).unwrap(),
+            expected()
+        );
+    }
+
+    #[test]
+    fn test_unterminated_header_line_is_limited() {
+        let mut parser = MultipartParser::new(b"boundary")
+            .unwrap()
+            .with_limits(ParserLimits {
+                max_header_size: Some(64),
+                ..ParserLimits::UNLIMITED
+            });
+        parser.feed(b"--boundary\r\n");
+        assert_eq!(parser.next_event().unwrap(), Some(Event::PartBegin));
+
+        let result = (0..100).try_for_each(|_| {
+            parser.feed(b"X-Header: aaaaaaaa");
+            parser.next_event().map(|_| ())
+        });
+        assert!(matches!(
+            result,
+            Err(MultipartError::LimitExceeded {
+                limit: Limit::HeaderSize,
+                max: 64,
+                offset: 12
+            })
+        ));
+    }
+
+    fn parse_with_mode(
+        body: &[u8],
+        boundary: &[u8],
+        chunk_size: usize,
+        mode: Mode,
+    ) -> Result<Vec<Repair>, MultipartError> {
+        let mut parser = MultipartParser::new(boundary)?.with_mode(mode);
+        for chunk in body.chunks(chunk_size) {
+            parser.feed(chunk);
+            while parser.next_event()?.is_some() {}
+        }
+        parser.finish();
+        while parser.next_event()?.is_some() {}
+
+        Ok(parser.repairs().to_vec())
+    }
+
+    type ModeCase = (
+        &'static [u8],
+        &'static [u8],
+        Repair,
+        fn(&MultipartError) -> bool,
+    );
+
+    #[test]
+    fn test_strict_and_lenient_mode() {
+        let cases: [ModeCase; 5] = [
+            (
+                b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--boundary--",
+                b"--boundary",
+                Repair::DelimiterWithoutDashes { offset: 0 },
+                |error| matches!(error, MultipartError::MissingClosingDelimiter { .. }),
+            ),
+            (
+                b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--boundary",
+                b"boundary",
+                Repair::UnterminatedClosingDelimiter { offset: 73 },
+                |error| matches!(error, MultipartError::MissingClosingDelimiter { .. }),
+            ),
+            (
+                b"--boundary\r\nContent-Disposition : form-data; name=\"a\"\r\n\r\nvalue\r\n--boundary--",
+                b"boundary",
+                Repair::HeaderNameWhitespace { offset: 12 },
+                |error| matches!(error, MultipartError::MalformedHeader { offset: 12 }),
+            ),
+            (
+                b"--boundary\r\nContent-Disposition: form-data;\n name=\"a\"\r\n\r\nvalue\r\n--boundary--",
+                b"boundary",
+                Repair::BareLineBreakInHeader { offset: 12 },
+                |error| matches!(error, MultipartError::MalformedHeader { offset: 12 }),
+            ),
+            (
+                b"--boundary\r\nContent-Type: text/plain\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\nvalue\r\n--boundary--",
+                b"boundary",
+                Repair::NonFormDataDisposition { offset: 38 },
+                |error| matches!(error, MultipartError::InvalidDisposition { offset: 38 }),
+            ),
+        ];
+
+        for (body, boundary, repair, is_expected_error) in cases {
+            for chunk_size in [1, 7, body.len()] {
+                assert_eq!(
+                    parse_with_mode(body, boundary, chunk_size, Mode::Lenient).unwrap(),
+                    [repair]
+                );
+                let error = parse_with_mode(body, boundary, chunk_size, Mode::Strict).unwrap_err();
+                assert!(is_expected_error(&error), "{:?}", error);
+            }
+        }
+
+        for chunk_size in [1, 7, BODY.len()] {
+            assert_eq!(
+                parse_with_mode(BODY, b"boundary", chunk_size, Mode::Strict).unwrap(),
+                []
+            );
+        }
+    }
+
+    #[test]
+    fn test_bare_lf_line_endings() {
+        let body: &[u8] = b"preamble\n--boundary\nContent-Disposition: form-data; name=\"field\"\r\n\nvalue\n--boundary \t\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\nContent-Type: text/plain\n\nline 1\r\n\r\nline 2 --boundar\r\n--boundary--\nepilogue";
+
+        for chunk_size in 1..=body.len() {
+            let mut parser = MultipartParser::new(b"boundary")
+                .unwrap()
+                .with_line_endings(LineEndings::Any);
+            let mut events = Vec::new();
+            for chunk in body.chunks(chunk_size) {
+                parser.feed(chunk);
+                while let Some(event) = parser.next_event().unwrap() {
+                    collect(&mut events, event);
+                }
+            }
+            parser.finish();
+            while let Some(event) = parser.next_event().unwrap() {
+                collect(&mut events, event);
+            }
+            assert_eq!(events, expected(), "chunk size {}", chunk_size);
+        }
+
+        // only the closing delimiter is preceded by a CRLF
+        assert_eq!(
+            parse_chunked(body, b"boundary", body.len()),
+            vec![Owned::End]
+        );
+    }
+
+    #[test]
+    fn test_bare_lf_keeps_content_exact() {
+        let body =
+            b"--boundary\nContent-Disposition: form-data; name=\"a\"\n\nline\n\n\n--boundary--";
+        let data: Vec<u8> = Events::new(body, b"boundary")
+            .unwrap()
+            .with_line_endings(LineEndings::Any)
+            .filter_map(|event| match event.unwrap() {
+                Event::Data(data) => Some(data.to_vec()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+
+        assert_eq!(data, b"line\n\n");
+    }
+}
@@ -0,0 +1,467 @@
+use crate::content_disposition::ContentDisposition;
+use crate::error::MultipartError;
+use crate::limits::ParserLimits;
+use crate::media_type::MediaType;
+use crate::mode::{LineEndings, Mode, Repair};
+use crate::parser::{header_lines, machine_options, Machine, RawEvent, Step};
+use crate::transfer_encoding::{decode, TransferEncoding};
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+use std::borrow::Cow;
+use std::ops::{Deref, Range};
+
+/// The header block and body of a part, as ranges of the input.
+#[derive(Debug)]
+struct Spans {
+    head: Range<usize>,
+    body: Range<usize>,
+}
+
+/// Drives a [`Machine`] over a complete body and collects the spans of each part.
+#[derive(Debug)]
+struct Scanner {
+    machine: Machine,
+    position: usize,
+    done: bool,
+}
+
+impl Scanner {
+    fn new(boundary: &[u8]) -> Result<Self, MultipartError> {
+        Ok(Self {
+            machine: Machine::new(boundary)?,
+            position: 0,
+            done: false,
+        })
+    }
+
+    fn next(&mut self, input: &[u8]) -> Option<Result<Spans, MultipartError>> {
+        let mut spans = Spans {
+            head: 0..0,
+            body: 0..0,
+        };
+
+        while !self.done {
+            let start = self.position;
+            match self.machine.step(&input[start..], true) {
+                Ok(Step::Event(event, consumed)) => {
+                    self.position += consumed;
+                    match event {
+                        RawEvent::PartBegin => spans.head = self.position..self.position,
+                        RawEvent::Header(..) => {}
+                        RawEvent::HeadersEnd => {
+                            spans.head.end = start;
+                            spans.body = self.position..self.position;
+                        }
+                        RawEvent::Data(range) => spans.body.end = start + range.end,
+                        RawEvent::PartEnd => return Some(Ok(spans)),
+                        RawEvent::End => self.done = true,
+                    }
+                }
+                Ok(Step::NeedMore(_)) => self.done = true,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The headers of a part, read in the mode and with the line endings of the machine.
+    fn headers<H: AsRef<[u8]>>(&self, head: H) -> PartHeaders<H> {
+        PartHeaders::new(head, self.machine.mode(), self.machine.line_endings())
+    }
+}
+
+/// The header block of a part, parsed on access, with accessors for the headers that describe the
+/// part. [`Part`], `BytesPart` and `AsyncPart` dereference to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartHeaders<H> {
+    head: H,
+    mode: Mode,
+    line_endings: LineEndings,
+}
+
+impl<H: AsRef<[u8]>> PartHeaders<H> {
+    pub(crate) fn new(head: H, mode: Mode, line_endings: LineEndings) -> Self {
+        Self {
+            head,
+            mode,
+            line_endings,
+        }
+    }
+
+    /// The headers of the part, in the order they were sent.
+    pub fn headers(&self) -> impl Iterator<Item = (Cow<'_, str>, Cow<'_, str>)> {
+        header_lines(self.head.as_ref(), self.line_endings)
+    }
+
+    /// Returns the value of the first header with the given (case-insensitive) name.
+    pub fn header(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.headers()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Parses the `Content-Disposition` header in the mode of the parser, which recorded what was
+    /// repaired.
+    pub(crate) fn parse_content_disposition(
+        &self,
+    ) -> Result<Option<ContentDisposition>, MultipartError> {
+        match self.header("content-disposition") {
+            Some(value) => ContentDisposition::parse_with_mode(&value, self.mode)
+                .map(|(disposition, _)| Some(disposition)),
+            None => Ok(None),
+        }
+    }
+
+    /// The parsed `Content-Disposition` header, `None` if it is missing or malformed.
+    pub fn content_disposition(&self) -> Option<ContentDisposition> {
+        self.parse_content_disposition().ok().flatten()
+    }
+
+    /// The field name given in the `Content-Disposition` header.
+    pub fn name(&self) -> Option<String> {
+        self.content_disposition()?.name().map(str::to_owned)
+    }
+
+    /// The filename given in the `Content-Disposition` header, preferring `filename*`.
+    pub fn filename(&self) -> Option<String> {
+        self.content_disposition()?
+            .preferred_filename()
+            .map(str::to_owned)
+    }
+
+    pub fn content_type(&self) -> Option<Cow<'_, str>> {
+        self.header("content-type")
+    }
+
+    /// The parsed `Content-Type` header, `None` if it is missing or malformed.
+    pub fn media_type(&self) -> Option<MediaType> {
+        MediaType::parse(&self.header("content-type")?).ok()
+    }
+
+    pub fn transfer_encoding(&self) -> Option<TransferEncoding> {
+        TransferEncoding::from_header(&self.header("content-transfer-encoding")?)
+    }
+
+    /// Decodes `body` if the part has a `Content-Transfer-Encoding`.
+    fn decode(&self, body: &[u8], offset: usize) -> Result<Option<Vec<u8>>, MultipartError> {
+        self.transfer_encoding()
+            .map(|encoding| decode(encoding, body, offset))
+            .transpose()
+    }
+}
+
+/// A part of a complete body that borrows its headers and body from the input.
+///
+/// Headers are parsed on access, so iterating over the parts of a body does not allocate. They
+/// are read through [`PartHeaders`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part<'a> {
+    head: PartHeaders<&'a [u8]>,
+    body: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Part<'a> {
+    /// The body as sent, i.e. before any `Content-Transfer-Encoding` is decoded.
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+
+    /// The body with its `Content-Transfer-Encoding` decoded. Only encoded bodies are copied.
+    pub fn decoded_body(&self) -> Result<Cow<'a, [u8]>, MultipartError> {
+        Ok(match self.head.decode(self.body, self.offset)? {
+            Some(decoded) => Cow::Owned(decoded),
+            None => Cow::Borrowed(self.body),
+        })
+    }
+}
+
+impl<'a> Deref for Part<'a> {
+    type Target = PartHeaders<&'a [u8]>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.head
+    }
+}
+
+/// Iterates over the parts of a complete body without copying it.
+///
+/// ```
+/// use fast_multipart_parser::Parts;
+///
+/// let body = b"--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--boundary--\r\n";
+///
+/// for part in Parts::new(body, b"boundary")? {
+///     let part = part?;
+///     assert_eq!(part.name().as_deref(), Some("field"));
+///     assert_eq!(part.body(), b"value");
+/// }
+/// # Ok::<(), fast_multipart_parser::MultipartError>(())
+/// ```
+#[derive(Debug)]
+pub struct Parts<'a> {
+    scanner: Scanner,
+    input: &'a [u8],
+}
+
+impl<'a> Parts<'a> {
+    pub fn new(body: &'a [u8], boundary: &[u8]) -> Result<Self, MultipartError> {
+        Ok(Self {
+            scanner: Scanner::new(boundary)?,
+            input: body,
+        })
+    }
+
+    machine_options!(scanner.machine);
+}
+
+impl<'a> Iterator for Parts<'a> {
+    type Item = Result<Part<'a>, MultipartError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.input;
+        let spans = self.scanner.next(input)?;
+        Some(spans.map(|spans| Part {
+            head: self.scanner.headers(&input[spans.head]),
+            offset: spans.body.start,
+            body: &input[spans.body],
+        }))
+    }
+}
+
+/// A part of a [`Bytes`] body. The headers and body share the buffer of the body, the headers
+/// are read through [`PartHeaders`].
+#[cfg(feature = "bytes")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BytesPart {
+    head: PartHeaders<Bytes>,
+    body: Bytes,
+    offset: usize,
+}
+
+#[cfg(feature = "bytes")]
+impl BytesPart {
+    /// The body as sent, i.e. before any `Content-Transfer-Encoding` is decoded.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// The body with its `Content-Transfer-Encoding` decoded. Only encoded bodies are copied.
+    pub fn decoded_body(&self) -> Result<Bytes, MultipartError> {
+        Ok(match self.head.decode(&self.body, self.offset)? {
+            Some(decoded) => decoded.into(),
+            None => self.body.clone(),
+        })
+    }
+
+    pub fn into_body(self) -> Bytes {
+        self.body
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Deref for BytesPart {
+    type Target = PartHeaders<Bytes>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.head
+    }
+}
+
+/// Iterates over the parts of a [`Bytes`] body, slicing the buffer instead of copying it.
+#[cfg(feature = "bytes")]
+#[derive(Debug)]
+pub struct BytesParts {
+    scanner: Scanner,
+    input: Bytes,
+}
+
+#[cfg(feature = "bytes")]
+impl BytesParts {
+    pub fn new(body: Bytes, boundary: &[u8]) -> Result<Self, MultipartError> {
+        Ok(Self {
+            scanner: Scanner::new(boundary)?,
+            input: body,
+        })
+    }
+
+    machine_options!(scanner.machine);
+}
+
+#[cfg(feature = "bytes")]
+impl Iterator for BytesParts {
+    type Item = Result<BytesPart, MultipartError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let spans = self.scanner.next(&self.input)?;
+        Some(spans.map(|spans| BytesPart {
+            head: self.scanner.headers(self.input.slice(spans.head)),
+            offset: spans.body.start,
+            body: self.input.slice(spans.body),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\nContent-Type: text/plain\r\n\r\nline 1\r\n\r\nline 2 --boundar\r\n--boundary\r\nContent-Disposition: form-data; name=\"empty\"\r\n\r\n\r\n--boundary--\r\nepilogue";
+
+    fn within(slice: &[u8], buffer: &[u8]) -> bool {
+        buffer.as_ptr_range().contains(&slice.as_ptr())
+    }
+
+    #[test]
+    fn test_parts_borrow_from_body() {
+        let parts: Vec<Part> = Parts::new(BODY, b"boundary")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(parts.len(), 3);
+
+        assert_eq!(parts[0].name().as_deref(), Some("field"));
+        assert_eq!(parts[0].filename(), None);
+        assert_eq!(parts[0].content_type(), None);
+        assert_eq!(parts[0].body(), b"value");
+
+        assert_eq!(
+            parts[1].headers().collect::<Vec<_>>(),
+            [
+                (
+                    Cow::Borrowed("Content-Disposition"),
+                    Cow::Borrowed("form-data; name=\"file\"; filename=\"file.txt\"")
+                ),
+                (Cow::Borrowed("Content-Type"), Cow::Borrowed("text/plain")),
+            ]
+        );
+        assert_eq!(parts[1].filename().as_deref(), Some("file.txt"));
+        assert_eq!(parts[1].content_type().as_deref(), Some("text/plain"));
+        assert_eq!(
+            parts[1].media_type().map(|media_type| media_type.essence()),
+            Some(String::from("text/plain"))
+        );
+        assert_eq!(
+            parts[1]
+                .content_disposition()
+                .map(|disposition| disposition.to_string()),
+            Some(String::from(
+                "form-data; name=\"file\"; filename=\"file.txt\""
+            ))
+        );
+        assert_eq!(parts[1].body(), b"line 1\r\n\r\nline 2 --boundar");
+
+        assert_eq!(parts[2].body(), b"");
+
+        for part in &parts {
+            assert!(within(part.body(), BODY) || part.body().is_empty());
+            for (name, value) in part.headers() {
+                assert!(matches!(
+                    (name, value),
+                    (Cow::Borrowed(_), Cow::Borrowed(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parts_report_errors() {
+        let mut parts = Parts::new(
+            b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue",
+            b"boundary",
+        )
+        .unwrap();
+        assert!(matches!(
+            parts.next(),
+            Some(Err(MultipartError::MissingClosingDelimiter { .. }))
+        ));
+        assert!(parts.next().is_none());
+    }
+
+    #[test]
+    fn test_strict_mode_requires_form_data() {
+        let body = b"--boundary\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\nvalue\r\n--boundary--\r\n";
+
+        let mut parts = Parts::new(body, b"boundary").unwrap();
+        assert!(parts.next().unwrap().is_ok());
+        assert_eq!(
+            parts.repairs(),
+            [Repair::NonFormDataDisposition { offset: 12 }]
+        );
+
+        let mut parts = Parts::new(body, b"boundary")
+            .unwrap()
+            .with_mode(Mode::Strict);
+        assert!(matches!(
+            parts.next(),
+            Some(Err(MultipartError::InvalidDisposition { offset: 12 }))
+        ));
+
+        #[cfg(feature = "bytes")]
+        {
+            let mut parts = BytesParts::new(Bytes::from_static(body), b"boundary")
+                .unwrap()
+                .with_mode(Mode::Strict);
+            assert!(matches!(
+                parts.next(),
+                Some(Err(MultipartError::InvalidDisposition { offset: 12 }))
+            ));
+        }
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_bytes_parts_share_buffer() {
+        let body = Bytes::from_static(BODY);
+        let parts: Vec<BytesPart> = BytesParts::new(body.clone(), b"boundary")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].name().as_deref(), Some("file"));
+        assert_eq!(parts[1].content_type().as_deref(), Some("text/plain"));
+        assert_eq!(parts[1].body(), &b"line 1\r\n\r\nline 2 --boundar"[..]);
+        assert!(within(parts[0].body(), &body));
+        assert!(within(parts[1].body(), &body));
+    }
+
+    #[test]
+    fn test_decoded_body() {
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\nContent-Transfer-Encoding: base64\r\n\r\nYWJj\r\nZGVm\r\n--boundary\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\nplain\r\n--boundary--\r\n";
+        let parts: Vec<Part> = Parts::new(body, b"boundary")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(parts[0].transfer_encoding(), Some(TransferEncoding::Base64));
+        assert_eq!(parts[0].body(), b"YWJj\r\nZGVm");
+        assert_eq!(parts[0].decoded_body().unwrap(), &b"abcdef"[..]);
+
+        assert_eq!(parts[1].transfer_encoding(), None);
+        assert!(matches!(
+            parts[1].decoded_body(),
+            Ok(Cow::Borrowed(b"plain"))
+        ));
+    }
+
+    #[test]
+    fn test_headers_with_bare_lf() {
+        let body = b"--boundary\nContent-Disposition: form-data; name=\"a\"\nContent-Type: text/plain\r\n\nvalue\n--boundary--\n";
+        let part = Parts::new(body, b"boundary")
+            .unwrap()
+            .with_line_endings(LineEndings::Any)
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(part.name().as_deref(), Some("a"));
+        assert_eq!(part.content_type().as_deref(), Some("text/plain"));
+        assert_eq!(part.headers().count(), 2);
+        assert_eq!(part.body(), b"value");
+    }
+}
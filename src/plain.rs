@@ -0,0 +1,248 @@
+use crate::error::MultipartError;
+use crate::form_data::FormData;
+use crate::limits::{check, check_body_size, Limit};
+use crate::mode::Mode;
+use crate::multipart::ParseOptions;
+use encoding_rs::{Encoding, UTF_8};
+use memchr::memmem;
+
+pub fn parse_text_plain_form(body: &[u8], charset: &[u8]) -> Result<FormData, MultipartError> {
+    parse_text_plain_form_with_options(body, charset, &ParseOptions::default())
+}
+
+/// Parses a `text/plain` form body, as submitted by HTML forms with `enctype="text/plain"`.
+///
+/// Browsers send each field as a `name=value` line ended by CRLF, without escaping anything. The
+/// format is therefore ambiguous: a value containing CRLF is indistinguishable from a following
+/// line, and a `=` may belong to the name or the value. In [`Mode::Lenient`], a line is split at
+/// its first `=`, a line without one is taken to continue the value of the previous field, and
+/// trailing empty lines are ignored. In [`Mode::Strict`], lines without exactly one `=` and bare CR
+/// or LF characters are rejected.
+///
+/// As for `application/x-www-form-urlencoded` bodies, only the limits on the body size, the
+/// number of fields, the field name length and the field size apply, and values are only parsed as
+/// JSON with [`JsonPolicy::Always`](crate::JsonPolicy::Always).
+pub fn parse_text_plain_form_with_options(
+    body: &[u8],
+    charset: &[u8],
+    options: &ParseOptions,
+) -> Result<FormData, MultipartError> {
+    let limits = &options.limits;
+    check_body_size(limits, body.len())?;
+
+    let strict = options.mode == Mode::Strict;
+    let mut content = body.strip_suffix(b"\r\n").unwrap_or(body);
+    if !strict {
+        // trailing empty lines do not continue the last value
+        while let Some(rest) = content.strip_suffix(b"\r\n") {
+            content = rest;
+        }
+    }
+    if content.is_empty() {
+        return Ok(FormData::new());
+    }
+    let mut fields: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    let mut start = 0;
+
+    loop {
+        let end = memmem::find(&content[start..], b"\r\n").map_or(content.len(), |end| start + end);
+        let line = &content[start..end];
+
+        if strict {
+            if let Some(position) = line.iter().position(|&b| b == b'\r' || b == b'\n') {
+                return Err(MultipartError::AmbiguousFormData {
+                    offset: start + position,
+                });
+            }
+        }
+        match line.iter().position(|&b| b == b'=') {
+            Some(equals) => {
+                let value = &line[equals + 1..];
+                if strict && value.contains(&b'=') {
+                    return Err(MultipartError::AmbiguousFormData { offset: start });
+                }
+                check(Limit::Parts, limits.max_parts, fields.len() + 1, start)?;
+                check(
+                    Limit::FieldNameLength,
+                    limits.max_field_name_length,
+                    equals,
+                    start,
+                )?;
+                check(Limit::FieldSize, limits.max_field_size, value.len(), start)?;
+                fields.push((&line[..equals], value.to_vec()));
+            }
+            None if strict => return Err(MultipartError::AmbiguousFormData { offset: start }),
+            None => match fields.last_mut() {
+                Some((_, value)) => {
+                    value.extend_from_slice(b"\r\n");
+                    value.extend_from_slice(line);
+                    check(Limit::FieldSize, limits.max_field_size, value.len(), start)?;
+                }
+                None => {
+                    check(Limit::Parts, limits.max_parts, 1, start)?;
+                    check(
+                        Limit::FieldNameLength,
+                        limits.max_field_name_length,
+                        line.len(),
+                        start,
+                    )?;
+                    fields.push((line, Vec::new()));
+                }
+            },
+        }
+        if end == content.len() {
+            break;
+        }
+        start = end + 2;
+    }
+
+    let encoding = Encoding::for_label(charset).unwrap_or(UTF_8);
+    let mut result = FormData::new();
+    for (name, value) in fields {
+        result.push_value(name, &value, encoding, options.json);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ParserLimits;
+    use crate::multipart::Field;
+
+    fn values(result: &FormData) -> Vec<(&str, &str)> {
+        result
+            .iter()
+            .map(|(name, field)| match field {
+                Field::String(field) => (name, field.content()),
+                other => panic!("{:?}", other),
+            })
+            .collect()
+    }
+
+    fn strict() -> ParseOptions {
+        ParseOptions {
+            mode: Mode::Strict,
+            ..ParseOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_text_plain_form() {
+        let body = b"name=J\xc3\xbcrgen\r\ntags=red\r\ntags=blue\r\nempty=\r\n";
+        let expected = [
+            ("name", "Jürgen"),
+            ("tags", "red"),
+            ("tags", "blue"),
+            ("empty", ""),
+        ];
+        assert_eq!(
+            values(&parse_text_plain_form(body, b"utf-8").unwrap()),
+            expected
+        );
+        assert_eq!(
+            values(&parse_text_plain_form_with_options(body, b"utf-8", &strict()).unwrap()),
+            expected
+        );
+
+        assert_eq!(
+            values(&parse_text_plain_form(b"a=1\r\nb=2", b"utf-8").unwrap()),
+            [("a", "1"), ("b", "2")]
+        );
+        assert_eq!(
+            values(&parse_text_plain_form(b"a=1\r\n\r\n", b"utf-8").unwrap()),
+            [("a", "1")]
+        );
+        assert!(parse_text_plain_form(b"", b"utf-8").unwrap().is_empty());
+        assert!(parse_text_plain_form(b"\r\n\r\n", b"utf-8")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            values(&parse_text_plain_form(b"caf\xe9=\xe9t\xe9\r\n", b"iso-8859-1").unwrap()),
+            [("café", "été")]
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_input() {
+        let body = b"comment=first line\r\nsecond line\r\nformula=a=b\r\nnote=bare\nlf\r\n";
+        assert_eq!(
+            values(&parse_text_plain_form(body, b"utf-8").unwrap()),
+            [
+                ("comment", "first line\r\nsecond line"),
+                ("formula", "a=b"),
+                ("note", "bare\nlf"),
+            ]
+        );
+        assert_eq!(
+            values(&parse_text_plain_form(b"orphan\r\na=1\r\n", b"utf-8").unwrap()),
+            [("orphan", ""), ("a", "1")]
+        );
+
+        for (body, expected_offset) in [
+            (&b"comment=first line\r\nsecond line\r\n"[..], 20),
+            (b"a=1\r\nformula=a=b\r\n", 5),
+            (b"a=1\r\nnote=bare\nlf\r\n", 14),
+            (b"a=1\r\r\n", 3),
+            (b"a=1\r\n\r\n", 5),
+        ] {
+            match parse_text_plain_form_with_options(body, b"utf-8", &strict()) {
+                Err(MultipartError::AmbiguousFormData { offset }) => {
+                    assert_eq!(offset, expected_offset)
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let body = b"a=1\r\nbb=22\r\nccc=333\r\n333\r\n";
+        let cases = [
+            (
+                ParserLimits {
+                    max_body_size: Some(10),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::BodySize,
+                10,
+            ),
+            (
+                ParserLimits {
+                    max_parts: Some(2),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::Parts,
+                12,
+            ),
+            (
+                ParserLimits {
+                    max_field_name_length: Some(2),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::FieldNameLength,
+                12,
+            ),
+            (
+                ParserLimits {
+                    max_field_size: Some(3),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::FieldSize,
+                21,
+            ),
+        ];
+        for (limits, expected_limit, expected_offset) in cases {
+            let options = ParseOptions {
+                limits,
+                ..ParseOptions::default()
+            };
+            match parse_text_plain_form_with_options(body, b"utf-8", &options) {
+                Err(MultipartError::LimitExceeded { limit, offset, .. }) => {
+                    assert_eq!((limit, offset), (expected_limit, expected_offset))
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// The content of an uploaded file, either held in memory or spooled to a temporary file.
+#[derive(Debug)]
+pub enum FileContent {
+    Memory(Vec<u8>),
+    /// The temporary file is deleted when it is dropped.
+    Spooled {
+        file: NamedTempFile,
+        size: usize,
+    },
+}
+
+impl FileContent {
+    pub fn len(&self) -> usize {
+        match self {
+            FileContent::Memory(content) => content.len(),
+            FileContent::Spooled { size, .. } => *size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The path of the temporary file, if the content was spooled.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FileContent::Memory(_) => None,
+            FileContent::Spooled { file, .. } => Some(file.path()),
+        }
+    }
+
+    /// Reads the whole content into memory.
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            FileContent::Memory(content) => Ok(content.clone()),
+            FileContent::Spooled { file, size } => read_file(file.as_file(), *size),
+        }
+    }
+}
+
+impl PartialEq for FileContent {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (FileContent::Memory(a), FileContent::Memory(b)) => a == b,
+            (FileContent::Spooled { file: a, .. }, FileContent::Spooled { file: b, .. }) => {
+                a.path() == b.path()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for FileContent {}
+
+impl IntoPy<PyObject> for FileContent {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        match self {
+            FileContent::Memory(content) => PyBytes::new(py, &content).into_py(py),
+            FileContent::Spooled { file, size } => SpooledFile {
+                file: Some(file),
+                size,
+            }
+            .into_py(py),
+        }
+    }
+}
+
+fn read_file(mut file: &File, size: usize) -> io::Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size);
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Collects the body of a file part, switching from memory to a temporary file once the
+/// threshold is crossed.
+#[derive(Debug)]
+pub(crate) struct Spool {
+    threshold: Option<usize>,
+    dir: Option<PathBuf>,
+    memory: Vec<u8>,
+    file: Option<NamedTempFile>,
+    size: usize,
+}
+
+impl Spool {
+    pub(crate) fn new(threshold: Option<usize>, dir: Option<PathBuf>) -> Self {
+        Self {
+            threshold,
+            dir,
+            memory: Vec::new(),
+            file: None,
+            size: 0,
+        }
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.size += data.len();
+
+        if self.file.is_none() && self.threshold.is_some_and(|max| self.size > max) {
+            let mut file = match &self.dir {
+                Some(dir) => NamedTempFile::new_in(dir)?,
+                None => NamedTempFile::new()?,
+            };
+            file.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some(file);
+        }
+
+        match &mut self.file {
+            Some(file) => file.write_all(data),
+            None => {
+                self.memory.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> io::Result<FileContent> {
+        Ok(match self.file {
+            Some(mut file) => {
+                file.flush()?;
+                FileContent::Spooled {
+                    file,
+                    size: self.size,
+                }
+            }
+            None => FileContent::Memory(self.memory),
+        })
+    }
+}
+
+/// A file that was spooled to disk. The file is deleted on `close` or once the object is garbage
+/// collected.
+#[pyclass(module = "fast_multipart_parser")]
+pub struct SpooledFile {
+    file: Option<NamedTempFile>,
+    size: usize,
+}
+
+impl SpooledFile {
+    fn file(&self) -> PyResult<&NamedTempFile> {
+        self.file
+            .as_ref()
+            .ok_or_else(|| PyValueError::new_err("I/O operation on closed file"))
+    }
+}
+
+#[pymethods]
+impl SpooledFile {
+    #[getter]
+    fn path(&self) -> PyResult<PathBuf> {
+        Ok(self.file()?.path().to_owned())
+    }
+
+    #[getter]
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.file.is_none()
+    }
+
+    /// Reads the whole file.
+    fn read<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        let content = read_file(self.file()?.as_file(), self.size)?;
+        Ok(PyBytes::new(py, &content))
+    }
+
+    /// Deletes the file.
+    fn close(&mut self) -> PyResult<()> {
+        match self.file.take() {
+            Some(file) => Ok(file.close()?),
+            None => Ok(()),
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.size
+    }
+
+    fn __repr__(&self) -> String {
+        match &self.file {
+            Some(file) => format!("<SpooledFile(path={:?}, size={})>", file.path(), self.size),
+            None => String::from("<SpooledFile(closed)>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stays_in_memory_up_to_threshold() {
+        let mut spool = Spool::new(Some(8), None);
+        spool.write(b"1234").unwrap();
+        spool.write(b"5678").unwrap();
+
+        assert_eq!(
+            spool.finish().unwrap(),
+            FileContent::Memory(b"12345678".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_spools_above_threshold_and_deletes_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::new(Some(8), Some(dir.path().to_owned()));
+        spool.write(b"1234").unwrap();
+        spool.write(b"56789").unwrap();
+        spool.write(b"0").unwrap();
+
+        let content = spool.finish().unwrap();
+        let path = content.path().unwrap().to_owned();
+        assert!(path.starts_with(dir.path()));
+        assert_eq!(content.len(), 10);
+        assert_eq!(content.read().unwrap(), b"1234567890");
+        assert_eq!(std::fs::read(&path).unwrap(), b"1234567890");
+
+        drop(content);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,459 @@
+use crate::content_disposition::ContentDisposition;
+use crate::error::MultipartError;
+use crate::limits::{FieldLimiter, ParserLimits};
+use crate::mode::{LineEndings, Mode, Repair};
+use crate::parser::{machine_options, Machine, RawEvent, Step};
+use crate::part::PartHeaders;
+use crate::transfer_encoding::Decoder;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_core::Stream;
+use std::error::Error;
+use std::future::poll_fn;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+type BodyStream = Pin<Box<dyn Stream<Item = Result<Bytes, BoxError>> + Send>>;
+
+enum OwnedEvent {
+    PartBegin,
+    /// A header line, with its line break.
+    Header(Bytes),
+    HeadersEnd,
+    Data(Bytes),
+    PartEnd,
+    End,
+}
+
+impl OwnedEvent {
+    /// Resolves an event in the `input` it consumed, of which part bodies are slices rather than
+    /// copies.
+    fn resolve(event: RawEvent, input: Bytes) -> Self {
+        match event {
+            RawEvent::PartBegin => OwnedEvent::PartBegin,
+            RawEvent::Header(..) => OwnedEvent::Header(input),
+            RawEvent::HeadersEnd => OwnedEvent::HeadersEnd,
+            RawEvent::Data(range) => OwnedEvent::Data(input.slice(range)),
+            RawEvent::PartEnd => OwnedEvent::PartEnd,
+            RawEvent::End => OwnedEvent::End,
+        }
+    }
+}
+
+/// An async multipart reader that yields the parts of a body one at a time.
+///
+/// The underlying source is only polled when the parser runs out of input, so a part body that is
+/// not being read applies backpressure to the source. Unread data of a part is skipped when the
+/// next part is requested, and counts towards the limits as if it was read. After an error, no
+/// more parts or chunks are returned.
+pub struct AsyncMultipart {
+    source: BodyStream,
+    machine: Machine,
+    buffer: BytesMut,
+    limiter: FieldLimiter,
+    decoder: Option<Decoder>,
+    head: BytesMut,
+    source_done: bool,
+    in_body: bool,
+    done: bool,
+}
+
+impl AsyncMultipart {
+    /// Creates a reader over a stream of body chunks, such as a hyper or axum body.
+    pub fn new<S, E>(stream: S, boundary: &[u8]) -> Result<Self, MultipartError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        Ok(Self {
+            source: Box::pin(MapErr(Box::pin(stream))),
+            machine: Machine::new(boundary)?,
+            buffer: BytesMut::new(),
+            limiter: FieldLimiter::new(ParserLimits::default()),
+            decoder: None,
+            head: BytesMut::new(),
+            source_done: false,
+            in_body: false,
+            done: false,
+        })
+    }
+
+    /// Creates a reader over an [`AsyncRead`].
+    pub fn with_reader<R>(reader: R, boundary: &[u8]) -> Result<Self, MultipartError>
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        Self::new(ReaderStream::new(reader), boundary)
+    }
+
+    /// Replaces the default [`ParserLimits`].
+    pub fn with_limits(mut self, limits: ParserLimits) -> Self {
+        self.machine.set_limits(limits);
+        self.limiter = FieldLimiter::new(limits);
+        self
+    }
+
+    machine_options!(machine; without limits);
+
+    /// Returns the next part, or `None` once the closing delimiter was read.
+    pub async fn next_part(&mut self) -> Result<Option<AsyncPart<'_>>, MultipartError> {
+        match poll_fn(|cx| self.poll_next_part(cx)).await? {
+            Some(head) => Ok(Some(AsyncPart { reader: self, head })),
+            None => Ok(None),
+        }
+    }
+
+    /// Ends the body after an error, so that no more parts or chunks are returned.
+    fn fail(&mut self, error: MultipartError) -> MultipartError {
+        self.done = true;
+        self.in_body = false;
+        error
+    }
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Result<OwnedEvent, MultipartError>> {
+        loop {
+            match self.machine.step(&self.buffer, self.source_done)? {
+                Step::Event(event, consumed) => {
+                    let input = self.buffer.split_to(consumed).freeze();
+                    return Poll::Ready(Ok(OwnedEvent::resolve(event, input)));
+                }
+                Step::NeedMore(consumed) => self.buffer.advance(consumed),
+            }
+            if self.source_done {
+                return Poll::Ready(Ok(OwnedEvent::End));
+            }
+
+            match ready!(self.source.as_mut().poll_next(cx)) {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(error)) => return Poll::Ready(Err(MultipartError::Stream(error))),
+                None => self.source_done = true,
+            }
+        }
+    }
+
+    fn poll_next_part(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<PartHeaders<Bytes>>, MultipartError>> {
+        let result = ready!(self.poll_headers(cx));
+        Poll::Ready(result.map_err(|error| self.fail(error)))
+    }
+
+    fn poll_headers(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<PartHeaders<Bytes>>, MultipartError>> {
+        while !self.done {
+            match ready!(self.poll_event(cx))? {
+                OwnedEvent::PartBegin => self.head.clear(),
+                OwnedEvent::Header(line) => self.head.extend_from_slice(&line),
+                OwnedEvent::HeadersEnd => {
+                    let head = PartHeaders::new(
+                        self.head.split().freeze(),
+                        self.machine.mode(),
+                        self.machine.line_endings(),
+                    );
+                    let offset = self.machine.offset();
+                    let disposition = head
+                        .parse_content_disposition()
+                        .map_err(|_| MultipartError::MalformedHeader { offset })?;
+                    self.limiter.begin(
+                        disposition.as_ref().and_then(ContentDisposition::name),
+                        disposition
+                            .as_ref()
+                            .is_some_and(|disposition| disposition.preferred_filename().is_some()),
+                        offset,
+                    )?;
+                    self.decoder = head.transfer_encoding().map(Decoder::new);
+                    self.in_body = true;
+                    return Poll::Ready(Ok(Some(head)));
+                }
+                // the unread body of the previous part
+                OwnedEvent::Data(data) => {
+                    self.decode(Some(data))?;
+                }
+                OwnedEvent::PartEnd if self.in_body => {
+                    self.in_body = false;
+                    self.decode(None)?;
+                }
+                OwnedEvent::PartEnd => {}
+                OwnedEvent::End => self.done = true,
+            }
+        }
+
+        Poll::Ready(Ok(None))
+    }
+
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, MultipartError>>> {
+        match ready!(self.poll_body(cx)) {
+            Some(Err(error)) => Poll::Ready(Some(Err(self.fail(error)))),
+            chunk => Poll::Ready(chunk),
+        }
+    }
+
+    fn poll_body(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, MultipartError>>> {
+        while self.in_body {
+            let chunk = match ready!(self.poll_event(cx))? {
+                OwnedEvent::Data(data) => self.decode(Some(data))?,
+                OwnedEvent::End => {
+                    self.in_body = false;
+                    self.done = true;
+                    continue;
+                }
+                _ => {
+                    self.in_body = false;
+                    self.decode(None)?
+                }
+            };
+            if !chunk.is_empty() {
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+        }
+
+        Poll::Ready(None)
+    }
+
+    /// Decodes a chunk of the part body, or what is left of it once `data` is `None`, and counts it
+    /// towards the limits.
+    fn decode(&mut self, data: Option<Bytes>) -> Result<Bytes, MultipartError> {
+        let offset = self.machine.offset();
+        let mut decoded = Vec::new();
+        let chunk = match (data, self.decoder.as_mut()) {
+            (Some(data), None) => data,
+            (Some(data), Some(decoder)) => {
+                decoder.decode(&data, &mut decoded, offset)?;
+                decoded.into()
+            }
+            (None, Some(decoder)) => {
+                decoder.finish(&mut decoded, offset)?;
+                decoded.into()
+            }
+            (None, None) => Bytes::new(),
+        };
+        self.limiter.data(chunk.len(), offset)?;
+        Ok(chunk)
+    }
+}
+
+/// A single part of an [`AsyncMultipart`] body, whose headers are read through [`PartHeaders`].
+/// The part body is a [`Stream`] of chunks.
+pub struct AsyncPart<'r> {
+    reader: &'r mut AsyncMultipart,
+    head: PartHeaders<Bytes>,
+}
+
+impl AsyncPart<'_> {
+    /// Reads the next chunk of the part body, or `None` once the body is complete.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        poll_fn(|cx| self.reader.poll_chunk(cx)).await.transpose()
+    }
+}
+
+impl Deref for AsyncPart<'_> {
+    type Target = PartHeaders<Bytes>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.head
+    }
+}
+
+impl Stream for AsyncPart<'_> {
+    type Item = Result<Bytes, MultipartError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().reader.poll_chunk(cx)
+    }
+}
+
+struct MapErr<S>(Pin<Box<S>>);
+
+impl<S, E> Stream for MapErr<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<BoxError>,
+{
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .0
+            .as_mut()
+            .poll_next(cx)
+            .map(|item| item.map(|result| result.map_err(Into::into)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer_encoding::TransferEncoding;
+    use futures_util::stream::{self, StreamExt};
+    use std::io;
+
+    const BODY: &[u8] = b"--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\nContent-Type: text/plain\r\n\r\n<file content>\r\n--boundary--\r\n";
+
+    fn chunked(body: &'static [u8], size: usize) -> AsyncMultipart {
+        AsyncMultipart::new(
+            stream::iter(
+                body.chunks(size)
+                    .map(|chunk| Ok::<_, io::Error>(Bytes::from(chunk))),
+            ),
+            b"boundary",
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reads_parts_from_stream() {
+        for size in [1, 7, BODY.len()] {
+            let mut multipart = chunked(BODY, size);
+
+            let mut field = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(field.name().as_deref(), Some("field"));
+            assert_eq!(field.filename(), None);
+            let mut content = Vec::new();
+            while let Some(chunk) = field.chunk().await.unwrap() {
+                content.extend_from_slice(&chunk);
+            }
+            assert_eq!(content, b"value");
+
+            let file = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(file.name().as_deref(), Some("file"));
+            assert_eq!(file.filename().as_deref(), Some("file.txt"));
+            assert_eq!(file.content_type().as_deref(), Some("text/plain"));
+            assert_eq!(file.headers().count(), 2);
+            let chunks: Vec<Bytes> = file.map(Result::unwrap).collect().await;
+            assert_eq!(chunks.concat(), b"<file content>");
+
+            assert!(multipart.next_part().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skips_unread_part_body() {
+        let mut multipart = chunked(BODY, 3);
+
+        multipart.next_part().await.unwrap().unwrap();
+        let file = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(file.name().as_deref(), Some("file"));
+    }
+
+    #[tokio::test]
+    async fn test_reads_parts_from_async_read() {
+        let mut multipart = AsyncMultipart::with_reader(BODY, b"boundary").unwrap();
+
+        let mut names = Vec::new();
+        while let Some(part) = multipart.next_part().await.unwrap() {
+            names.push(part.name().unwrap());
+        }
+        assert_eq!(names, ["field", "file"]);
+    }
+
+    #[tokio::test]
+    async fn test_propagates_source_errors() {
+        let mut multipart = AsyncMultipart::new(
+            stream::iter([
+                Ok(Bytes::from_static(
+                    b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nabc",
+                )),
+                Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+            ]),
+            b"boundary",
+        )
+        .unwrap();
+
+        let mut part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.chunk().await.unwrap().unwrap(), "abc");
+        assert!(matches!(
+            part.chunk().await,
+            Err(MultipartError::Stream(error)) if error.to_string() == "reset"
+        ));
+        assert!(part.chunk().await.unwrap().is_none());
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_enforces_limits() {
+        let mut multipart = chunked(BODY, 5).with_limits(ParserLimits {
+            max_file_size: Some(10),
+            ..ParserLimits::UNLIMITED
+        });
+
+        multipart.next_part().await.unwrap().unwrap();
+        let mut file = multipart.next_part().await.unwrap().unwrap();
+        let result = loop {
+            match file.chunk().await {
+                Ok(Some(_)) => continue,
+                result => break result,
+            }
+        };
+        assert!(matches!(
+            result,
+            Err(MultipartError::LimitExceeded {
+                limit: crate::limits::Limit::FileSize,
+                max: 10,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_counts_skipped_part_bodies() {
+        let mut multipart = chunked(BODY, 5).with_limits(ParserLimits {
+            max_field_size: Some(3),
+            ..ParserLimits::UNLIMITED
+        });
+
+        multipart.next_part().await.unwrap().unwrap();
+        assert!(matches!(
+            multipart.next_part().await,
+            Err(MultipartError::LimitExceeded {
+                limit: crate::limits::Limit::FieldSize,
+                max: 3,
+                ..
+            })
+        ));
+        // the body is not read any further after an error
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_requires_form_data() {
+        const ATTACHMENT: &[u8] = b"--boundary\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\nvalue\r\n--boundary--\r\n";
+
+        let mut multipart = chunked(ATTACHMENT, 5);
+        assert!(multipart.next_part().await.unwrap().is_some());
+        assert_eq!(
+            multipart.repairs(),
+            [Repair::NonFormDataDisposition { offset: 12 }]
+        );
+
+        let mut multipart = chunked(ATTACHMENT, 5).with_mode(Mode::Strict);
+        assert!(matches!(
+            multipart.next_part().await,
+            Err(MultipartError::InvalidDisposition { offset: 12 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decodes_transfer_encoding() {
+        const ENCODED: &[u8] = b"--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Transfer-Encoding: base64\r\n\r\nPGZpbGUg\r\nY29udGVudD4=\r\n--boundary\r\nContent-Disposition: form-data; name=\"text\"\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\ncaf=C3=A9=\r\n!\r\n--boundary--\r\n";
+
+        for size in [1, 5, ENCODED.len()] {
+            let mut multipart = chunked(ENCODED, size);
+
+            let file = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(file.transfer_encoding(), Some(TransferEncoding::Base64));
+            let chunks: Vec<Bytes> = file.map(Result::unwrap).collect().await;
+            assert_eq!(chunks.concat(), b"<file content>");
+
+            let text = multipart.next_part().await.unwrap().unwrap();
+            let chunks: Vec<Bytes> = text.map(Result::unwrap).collect().await;
+            assert_eq!(chunks.concat(), "café!".as_bytes());
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use crate::error::MultipartError;
+use std::fmt;
+
+/// A `Content-Transfer-Encoding` (RFC 2045) that is decoded by the parser. The identity encodings
+/// `7bit`, `8bit` and `binary` need no decoding and are not represented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferEncoding {
+    Base64,
+    QuotedPrintable,
+}
+
+impl TransferEncoding {
+    /// Parses the value of a `Content-Transfer-Encoding` header. Returns `None` for the identity
+    /// encodings and unknown values, whose content is passed through as is.
+    pub fn from_header(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("base64") {
+            Some(TransferEncoding::Base64)
+        } else if value.eq_ignore_ascii_case("quoted-printable") {
+            Some(TransferEncoding::QuotedPrintable)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferEncoding::Base64 => "base64",
+            TransferEncoding::QuotedPrintable => "quoted-printable",
+        }
+    }
+}
+
+impl fmt::Display for TransferEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[inline]
+fn base64_value(byte: u8) -> Option<u32> {
+    match byte {
+        b'A'..=b'Z' => Some((byte - b'A') as u32),
+        b'a'..=b'z' => Some((byte - b'a' + 26) as u32),
+        b'0'..=b'9' => Some((byte - b'0' + 52) as u32),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+#[inline]
+pub(crate) fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        _ => None,
+    }
+}
+
+/// An incremental decoder for a part body. Input may be split at any byte, incomplete base64
+/// quanta and quoted-printable sequences are carried over to the next chunk.
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    encoding: TransferEncoding,
+    // base64 state
+    bits: u32,
+    count: u8,
+    padding: u8,
+    // quoted-printable input that could not be decoded yet
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    pub(crate) fn new(encoding: TransferEncoding) -> Self {
+        Self {
+            encoding,
+            bits: 0,
+            count: 0,
+            padding: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Decodes a chunk of the body into `out`. `offset` is only used for error reporting.
+    pub(crate) fn decode(
+        &mut self,
+        data: &[u8],
+        out: &mut Vec<u8>,
+        offset: usize,
+    ) -> Result<(), MultipartError> {
+        match self.encoding {
+            TransferEncoding::Base64 => self.base64(data, out),
+            TransferEncoding::QuotedPrintable => {
+                let mut input = std::mem::take(&mut self.pending);
+                input.extend_from_slice(data);
+                self.quoted_printable(&input, out, false)
+            }
+        }
+        .map_err(|_| self.error(offset))
+    }
+
+    /// Decodes what is left once the body is complete.
+    pub(crate) fn finish(
+        &mut self,
+        out: &mut Vec<u8>,
+        offset: usize,
+    ) -> Result<(), MultipartError> {
+        match self.encoding {
+            TransferEncoding::Base64 => match (self.count, self.padding) {
+                (0, 0) => Ok(()),
+                // unpadded input is tolerated
+                (2, 0) => {
+                    out.push((self.bits >> 4) as u8);
+                    Ok(())
+                }
+                (3, 0) => {
+                    out.extend_from_slice(&[(self.bits >> 10) as u8, (self.bits >> 2) as u8]);
+                    Ok(())
+                }
+                _ => Err(()),
+            },
+            TransferEncoding::QuotedPrintable => {
+                let input = std::mem::take(&mut self.pending);
+                self.quoted_printable(&input, out, true)
+            }
+        }
+        .map_err(|_| self.error(offset))
+    }
+
+    fn error(&self, offset: usize) -> MultipartError {
+        MultipartError::MalformedEncoding {
+            encoding: self.encoding,
+            offset,
+        }
+    }
+
+    fn base64(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), ()> {
+        out.reserve(data.len() / 4 * 3);
+
+        for &byte in data {
+            match byte {
+                b'\r' | b'\n' | b' ' | b'\t' => continue,
+                b'=' if self.count >= 2 => self.padding += 1,
+                _ if self.padding > 0 => return Err(()),
+                _ => {
+                    self.bits = self.bits << 6 | base64_value(byte).ok_or(())?;
+                    self.count += 1;
+                }
+            }
+
+            if self.count + self.padding == 4 {
+                let bytes = (self.bits << (6 * self.padding as u32)).to_be_bytes();
+                out.extend_from_slice(&bytes[1..4 - self.padding as usize]);
+                self.bits = 0;
+                self.count = 0;
+                self.padding = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn quoted_printable(&mut self, input: &[u8], out: &mut Vec<u8>, eof: bool) -> Result<(), ()> {
+        out.reserve(input.len());
+        let mut index = 0;
+
+        while index < input.len() {
+            match input[index] {
+                b'=' => match input.get(index + 1..(index + 3).min(input.len())) {
+                    Some([b'\r', b'\n']) => index += 3,
+                    Some([b'\n', ..]) => index += 2,
+                    Some(&[high, low]) => {
+                        let high = hex_value(high).ok_or(())?;
+                        let low = hex_value(low).ok_or(())?;
+                        out.push(high << 4 | low);
+                        index += 3;
+                    }
+                    // the sequence continues in the next chunk
+                    _ if !eof => break,
+                    _ => return Err(()),
+                },
+                b' ' | b'\t' => {
+                    let end = input[index..]
+                        .iter()
+                        .position(|&b| b != b' ' && b != b'\t')
+                        .map_or(input.len(), |position| index + position);
+
+                    match &input[end..] {
+                        // trailing whitespace of a line is not part of the content
+                        [] if eof => {}
+                        [] | [b'\r'] if !eof => break,
+                        [b'\r', b'\n', ..] | [b'\n', ..] => {}
+                        _ => out.extend_from_slice(&input[index..end]),
+                    }
+                    index = end;
+                }
+                byte => {
+                    out.push(byte);
+                    index += 1;
+                }
+            }
+        }
+
+        self.pending.extend_from_slice(&input[index..]);
+        Ok(())
+    }
+}
+
+/// Decodes a complete body.
+pub(crate) fn decode(
+    encoding: TransferEncoding,
+    data: &[u8],
+    offset: usize,
+) -> Result<Vec<u8>, MultipartError> {
+    let mut decoder = Decoder::new(encoding);
+    let mut out = Vec::new();
+    decoder.decode(data, &mut out, offset)?;
+    decoder.finish(&mut out, offset)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_chunked(
+        encoding: TransferEncoding,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Result<Vec<u8>, MultipartError> {
+        let mut decoder = Decoder::new(encoding);
+        let mut out = Vec::new();
+        for chunk in data.chunks(chunk_size) {
+            decoder.decode(chunk, &mut out, 0)?;
+        }
+        decoder.finish(&mut out, 0)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_base64() {
+        let cases: [(&[u8], &[u8]); 6] = [
+            (b"", b""),
+            (b"YQ==", b"a"),
+            (b"YWI=", b"ab"),
+            (b"YWJj", b"abc"),
+            (b"YWJj\r\nZGVm\r\nZw==\r\n", b"abcdefg"),
+            (b"AP8Q/w", b"\x00\xff\x10\xff"),
+        ];
+
+        for (encoded, decoded) in cases {
+            for chunk_size in 1..=encoded.len().max(1) {
+                assert_eq!(
+                    decode_chunked(TransferEncoding::Base64, encoded, chunk_size).unwrap(),
+                    decoded
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_base64() {
+        for encoded in [&b"YW*j"[..], b"Y", b"YQ=a", b"Y==="] {
+            assert!(matches!(
+                decode_chunked(TransferEncoding::Base64, encoded, 1),
+                Err(MultipartError::MalformedEncoding {
+                    encoding: TransferEncoding::Base64,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_quoted_printable() {
+        let cases: [(&[u8], &[u8]); 5] = [
+            (b"plain text", b"plain text"),
+            (b"caf=C3=A9 =3D 100=25", "café = 100%".as_bytes()),
+            (b"soft =\r\nline break", b"soft line break"),
+            (b"trailing  \r\nspace \t", b"trailing\r\nspace"),
+            (
+                b"lower =c3=a9, bare lf=\nbreak",
+                "lower é, bare lfbreak".as_bytes(),
+            ),
+        ];
+
+        for (encoded, decoded) in cases {
+            for chunk_size in 1..=encoded.len() {
+                assert_eq!(
+                    decode_chunked(TransferEncoding::QuotedPrintable, encoded, chunk_size).unwrap(),
+                    decoded,
+                    "chunk size {}",
+                    chunk_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_quoted_printable() {
+        for encoded in [&b"=ZZ"[..], b"abc=", b"abc=4"] {
+            assert!(matches!(
+                decode_chunked(TransferEncoding::QuotedPrintable, encoded, 1),
+                Err(MultipartError::MalformedEncoding {
+                    encoding: TransferEncoding::QuotedPrintable,
+                    ..
+                })
+            ));
+        }
+    }
+}
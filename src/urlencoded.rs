@@ -0,0 +1,190 @@
+use crate::error::MultipartError;
+use crate::form_data::FormData;
+use crate::limits::{check, check_body_size, Limit};
+use crate::multipart::ParseOptions;
+use encoding_rs::{Encoding, UTF_8};
+use percent_encoding::percent_decode;
+use std::borrow::Cow;
+
+/// Replaces `+` with a space and decodes percent escapes, in this order so that `%2B` stays a `+`.
+fn unescape(input: &[u8]) -> Vec<u8> {
+    let input: Cow<'_, [u8]> = if input.contains(&b'+') {
+        input
+            .iter()
+            .map(|&b| if b == b'+' { b' ' } else { b })
+            .collect()
+    } else {
+        Cow::Borrowed(input)
+    };
+    percent_decode(&input).collect()
+}
+
+pub fn parse_urlencoded_form(body: &[u8], charset: &[u8]) -> Result<FormData, MultipartError> {
+    parse_urlencoded_form_with_options(body, charset, &ParseOptions::default())
+}
+
+/// Parses an `application/x-www-form-urlencoded` body into the same fields as a multipart body.
+///
+/// The limits on the body size, the number of fields (`max_parts`), the field name length and the
+/// field size apply. As the values have no content type, they are only parsed as JSON with
+/// [`JsonPolicy::Always`](crate::JsonPolicy::Always). Offsets in errors are those of the
+/// `name=value` pair.
+pub fn parse_urlencoded_form_with_options(
+    body: &[u8],
+    charset: &[u8],
+    options: &ParseOptions,
+) -> Result<FormData, MultipartError> {
+    let limits = &options.limits;
+    check_body_size(limits, body.len())?;
+
+    let encoding = Encoding::for_label(charset).unwrap_or(UTF_8);
+    let mut result = FormData::new();
+    let mut offset = 0;
+
+    for pair in body.split(|&b| b == b'&') {
+        let start = offset;
+        offset += pair.len() + 1;
+        if pair.is_empty() {
+            continue;
+        }
+        check(Limit::Parts, limits.max_parts, result.len() + 1, start)?;
+
+        let (name, value) = match pair.iter().position(|&b| b == b'=') {
+            Some(equals) => (&pair[..equals], &pair[equals + 1..]),
+            None => (pair, &b""[..]),
+        };
+        let name = unescape(name);
+        check(
+            Limit::FieldNameLength,
+            limits.max_field_name_length,
+            name.len(),
+            start,
+        )?;
+        let value = unescape(value);
+        check(Limit::FieldSize, limits.max_field_size, value.len(), start)?;
+
+        result.push_value(&name, &value, encoding, options.json);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ParserLimits;
+    use crate::multipart::{Field, JsonPolicy};
+
+    fn values(result: &FormData) -> Vec<(&str, &str)> {
+        result
+            .iter()
+            .map(|(name, field)| match field {
+                Field::String(field) => (name, field.content()),
+                other => panic!("{:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_urlencoded_form() {
+        let result = parse_urlencoded_form(
+            b"name=J%C3%BCrgen+M%2B&tags=red&&tags=blue&empty=&flag&a=b=c",
+            b"utf-8",
+        )
+        .unwrap();
+
+        assert_eq!(
+            values(&result),
+            [
+                ("name", "Jürgen M+"),
+                ("tags", "red"),
+                ("tags", "blue"),
+                ("empty", ""),
+                ("flag", ""),
+                ("a", "b=c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_charset() {
+        let result = parse_urlencoded_form(b"caf%E9=%E9t%E9", b"iso-8859-1").unwrap();
+        assert_eq!(values(&result), [("café", "été")]);
+
+        match parse_urlencoded_form(b"a=%E9", b"utf-8").unwrap().get("a") {
+            Some(Field::String(field)) => {
+                assert_eq!(field.content(), "\u{fffd}");
+                assert!(field.had_replacements());
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_json_policy() {
+        let body = b"zip=01234&count=12345";
+        assert_eq!(
+            values(&parse_urlencoded_form(body, b"utf-8").unwrap()),
+            [("zip", "01234"), ("count", "12345")]
+        );
+
+        let options = ParseOptions {
+            json: JsonPolicy::Always,
+            ..ParseOptions::default()
+        };
+        let result = parse_urlencoded_form_with_options(body, b"utf-8", &options).unwrap();
+        assert!(matches!(result.get("zip"), Some(Field::String(_))));
+        assert!(matches!(result.get("count"), Some(Field::Json(_))));
+    }
+
+    #[test]
+    fn test_limits() {
+        let body = b"a=1&bb=22&ccc=333";
+        let cases = [
+            (
+                ParserLimits {
+                    max_body_size: Some(10),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::BodySize,
+                10,
+            ),
+            (
+                ParserLimits {
+                    max_parts: Some(2),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::Parts,
+                10,
+            ),
+            (
+                ParserLimits {
+                    max_field_name_length: Some(2),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::FieldNameLength,
+                10,
+            ),
+            (
+                ParserLimits {
+                    max_field_size: Some(1),
+                    ..ParserLimits::UNLIMITED
+                },
+                Limit::FieldSize,
+                4,
+            ),
+        ];
+        for (limits, expected_limit, expected_offset) in cases {
+            let options = ParseOptions {
+                limits,
+                ..ParseOptions::default()
+            };
+            match parse_urlencoded_form_with_options(body, b"utf-8", &options) {
+                Err(MultipartError::LimitExceeded { limit, offset, .. }) => {
+                    assert_eq!((limit, offset), (expected_limit, expected_offset))
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
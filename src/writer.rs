@@ -0,0 +1,508 @@
+use crate::content_disposition::ContentDisposition;
+use crate::content_header::is_token;
+use crate::error::MultipartError;
+use crate::mode::Mode;
+use crate::parser::validate_boundary;
+use memchr::memmem;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{ready, Context, Poll};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, ReadBuf};
+
+fn random_boundary() -> String {
+    format!(
+        "----FastMultipartBoundary{:016x}{:016x}",
+        fastrand::u64(..),
+        fastrand::u64(..)
+    )
+}
+
+/// Names and filenames are written as browsers do, see [`ContentDisposition`].
+fn content_disposition(name: &str, filename: Option<&str>) -> String {
+    let disposition = ContentDisposition::form_data(name);
+    match filename {
+        Some(filename) => disposition.with_filename(filename).to_string(),
+        None => disposition.to_string(),
+    }
+}
+
+enum Source {
+    Memory(Cursor<Vec<u8>>),
+    Reader(Box<dyn Read + Send>),
+    #[cfg(feature = "async")]
+    AsyncReader(Pin<Box<dyn AsyncRead + Send>>),
+}
+
+impl Source {
+    fn memory(data: Vec<u8>) -> Self {
+        Source::Memory(Cursor::new(data))
+    }
+}
+
+struct WriterPart {
+    headers: String,
+    body: Source,
+}
+
+/// Builds a `multipart/form-data` body.
+///
+/// Parts are written in the order they were added. The body is produced by [`into_vec`], or read
+/// incrementally through the [`MultipartReader`] returned by [`into_reader`], which only reads
+/// file sources as the body is consumed.
+///
+/// ```
+/// use fast_multipart_parser::MultipartWriter;
+///
+/// let mut writer = MultipartWriter::new();
+/// writer
+///     .add_text("field", "value")
+///     .add_file("file", "résumé.txt", Some("text/plain"), b"content".to_vec());
+///
+/// let content_type = writer.content_type();
+/// let body = writer.into_vec()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// [`into_vec`]: MultipartWriter::into_vec
+/// [`into_reader`]: MultipartWriter::into_reader
+pub struct MultipartWriter {
+    boundary: String,
+    random: bool,
+    parts: Vec<WriterPart>,
+}
+
+impl MultipartWriter {
+    /// Creates a writer with a random boundary. The boundary is replaced whenever it occurs in
+    /// the content of a part that is added from memory, so [`content_type`] must be read once
+    /// every part was added. Content of readers can not be checked, but a collision with the 128
+    /// random bits of the boundary is not a practical concern.
+    ///
+    /// [`content_type`]: MultipartWriter::content_type
+    pub fn new() -> Self {
+        Self {
+            boundary: random_boundary(),
+            random: true,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Creates a writer with the given boundary, which is used as is: it is up to the caller to
+    /// pick one that does not occur in the content. It must follow RFC 2046, which strict
+    /// recipients enforce.
+    pub fn with_boundary(boundary: &str) -> Result<Self, MultipartError> {
+        validate_boundary(boundary.as_bytes(), Mode::Strict)?;
+
+        Ok(Self {
+            boundary: boundary.to_owned(),
+            random: false,
+            parts: Vec::new(),
+        })
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// The value of the `Content-Type` header for the body.
+    pub fn content_type(&self) -> String {
+        if is_token(&self.boundary) {
+            format!("multipart/form-data; boundary={}", self.boundary)
+        } else {
+            format!("multipart/form-data; boundary=\"{}\"", self.boundary)
+        }
+    }
+
+    fn contains_boundary(&self, data: &[u8]) -> bool {
+        memmem::find(data, self.boundary.as_bytes()).is_some()
+    }
+
+    fn push(&mut self, headers: String, body: Source) -> &mut Self {
+        if self.random {
+            let collides = |writer: &Self, headers: &str, body: &Source| {
+                writer.contains_boundary(headers.as_bytes())
+                    || matches!(body, Source::Memory(data) if writer.contains_boundary(data.get_ref()))
+            };
+
+            // earlier parts are only checked again once the boundary was replaced
+            while collides(self, &headers, &body) {
+                self.boundary = random_boundary();
+                while self
+                    .parts
+                    .iter()
+                    .any(|part| collides(self, &part.headers, &part.body))
+                {
+                    self.boundary = random_boundary();
+                }
+            }
+        }
+
+        self.parts.push(WriterPart { headers, body });
+        self
+    }
+
+    fn file_headers(name: &str, filename: &str, content_type: Option<&str>) -> String {
+        format!(
+            "Content-Disposition: {}\r\nContent-Type: {}\r\n",
+            content_disposition(name, Some(filename)),
+            content_type.unwrap_or("application/octet-stream")
+        )
+    }
+
+    /// Adds a text field. Like browsers, no `Content-Type` is sent, which implies UTF-8 text.
+    pub fn add_text(&mut self, name: &str, value: &str) -> &mut Self {
+        let headers = format!(
+            "Content-Disposition: {}\r\n",
+            content_disposition(name, None)
+        );
+        self.push(headers, Source::memory(value.as_bytes().to_vec()))
+    }
+
+    /// Adds a field with `Content-Type: application/json`.
+    pub fn add_json(&mut self, name: &str, value: &Value) -> &mut Self {
+        let headers = format!(
+            "Content-Disposition: {}\r\nContent-Type: application/json\r\n",
+            content_disposition(name, None)
+        );
+        self.push(headers, Source::memory(value.to_string().into_bytes()))
+    }
+
+    /// Adds a file from memory. The content type defaults to `application/octet-stream`.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        filename: &str,
+        content_type: Option<&str>,
+        content: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        let headers = Self::file_headers(name, filename, content_type);
+        self.push(headers, Source::memory(content.into()))
+    }
+
+    /// Adds a file whose content is read from `reader` while the body is read. It can only be
+    /// read through the [`Read`] implementation of [`MultipartReader`] (or [`into_vec`]).
+    ///
+    /// [`into_vec`]: MultipartWriter::into_vec
+    pub fn add_reader(
+        &mut self,
+        name: &str,
+        filename: &str,
+        content_type: Option<&str>,
+        reader: impl Read + Send + 'static,
+    ) -> &mut Self {
+        let headers = Self::file_headers(name, filename, content_type);
+        self.push(headers, Source::Reader(Box::new(reader)))
+    }
+
+    /// Adds the file at `path`, which is opened right away but only read while the body is read.
+    /// The filename is the last component of the path.
+    pub fn add_path(
+        &mut self,
+        name: &str,
+        path: impl AsRef<Path>,
+        content_type: Option<&str>,
+    ) -> io::Result<&mut Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let filename = path
+            .file_name()
+            .map(|filename| filename.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(self.add_reader(name, &filename, content_type, file))
+    }
+
+    /// Adds a file whose content is read from an [`AsyncRead`], e.g. a `tokio::fs::File`. It can
+    /// only be read through the [`AsyncRead`] implementation of [`MultipartReader`].
+    #[cfg(feature = "async")]
+    pub fn add_async_reader(
+        &mut self,
+        name: &str,
+        filename: &str,
+        content_type: Option<&str>,
+        reader: impl AsyncRead + Send + 'static,
+    ) -> &mut Self {
+        let headers = Self::file_headers(name, filename, content_type);
+        self.push(headers, Source::AsyncReader(Box::pin(reader)))
+    }
+
+    /// Writes the whole body into memory.
+    pub fn into_vec(self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.into_reader().read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    /// Returns a reader over the body.
+    pub fn into_reader(self) -> MultipartReader {
+        let mut sources = VecDeque::with_capacity(self.parts.len() * 3 + 1);
+        for part in self.parts {
+            let head = format!("--{}\r\n{}\r\n", self.boundary, part.headers);
+            sources.push_back(Source::memory(head.into_bytes()));
+            sources.push_back(part.body);
+            sources.push_back(Source::memory(b"\r\n".to_vec()));
+        }
+        sources.push_back(Source::memory(
+            format!("--{}--\r\n", self.boundary).into_bytes(),
+        ));
+
+        MultipartReader { sources }
+    }
+}
+
+impl Default for MultipartWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads a body built by a [`MultipartWriter`].
+pub struct MultipartReader {
+    sources: VecDeque<Source>,
+}
+
+#[cfg(feature = "async")]
+fn wrong_reader(expected: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "the part can only be read through the {} implementation",
+            expected
+        ),
+    )
+}
+
+impl Read for MultipartReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(source) = self.sources.front_mut() {
+            let read = match source {
+                Source::Memory(data) => data.read(buf)?,
+                Source::Reader(reader) => reader.read(buf)?,
+                #[cfg(feature = "async")]
+                Source::AsyncReader(_) => return Err(wrong_reader("AsyncRead")),
+            };
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            self.sources.pop_front();
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncRead for MultipartReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while let Some(source) = this.sources.front_mut() {
+            let filled = buf.filled().len();
+            match source {
+                Source::Memory(data) => {
+                    let read = data.read(buf.initialize_unfilled())?;
+                    buf.advance(read);
+                }
+                Source::Reader(_) => return Poll::Ready(Err(wrong_reader("Read"))),
+                Source::AsyncReader(reader) => ready!(reader.as_mut().poll_read(cx, buf))?,
+            }
+            if buf.filled().len() > filled || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            this.sources.pop_front();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multipart::{parse_multipart_form_data, Field};
+    use crate::spool::FileContent;
+
+    #[test]
+    fn test_body_layout() {
+        let mut writer = MultipartWriter::with_boundary("boundary").unwrap();
+        writer
+            .add_text("field", "value")
+            .add_json("data", &serde_json::json!({"a": 1}))
+            .add_file("file", "a \"b\".txt", None, b"\x00\xff".to_vec());
+
+        assert_eq!(
+            writer.content_type(),
+            "multipart/form-data; boundary=boundary"
+        );
+        assert_eq!(
+            writer.into_vec().unwrap(),
+            b"--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--boundary\r\nContent-Disposition: form-data; name=\"data\"\r\nContent-Type: application/json\r\n\r\n{\"a\":1}\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a %22b%22.txt\"\r\nContent-Type: application/octet-stream\r\n\r\n\x00\xff\r\n--boundary--\r\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = MultipartWriter::new();
+        writer
+            .add_text("field", "välue")
+            .add_json("data", &serde_json::json!([1, null]))
+            .add_file(
+                "file",
+                "画像 1.jpg",
+                Some("image/jpeg"),
+                b"\r\n--\r\n".to_vec(),
+            )
+            .add_reader("reader", "b.bin", None, Cursor::new(vec![1u8; 10000]));
+
+        let boundary = writer.boundary().to_owned();
+        let result =
+            parse_multipart_form_data(&writer.into_vec().unwrap(), boundary.as_bytes(), b"utf-8")
+                .unwrap();
+
+        match result.get("field") {
+            Some(Field::String(field)) => assert_eq!(field.content(), "välue"),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(result.get("data"), Some(Field::Json(_))));
+        match result.get("file") {
+            Some(Field::File(file)) => {
+                assert_eq!(file.filename(), "画像 1.jpg");
+                assert_eq!(file.content(), &FileContent::Memory(b"\r\n--\r\n".to_vec()));
+            }
+            other => panic!("{:?}", other),
+        }
+        match result.get("reader") {
+            Some(Field::File(file)) => assert_eq!(file.content().len(), 10000),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_names_and_filenames_round_trip() {
+        // `"`, CR and LF arrive percent encoded, as from a browser
+        let sent = [
+            ("välue", "画像.jpg", "välue", "画像.jpg"),
+            ("a \"b\"", "a \"b\".txt", "a %22b%22", "a %22b%22.txt"),
+            ("back\\slash", "c:\\d.txt", "back\\slash", "c:\\d.txt"),
+            (
+                "line\r\nbreak",
+                "tab\tand\r\nbreak.txt",
+                "line%0D%0Abreak",
+                "tab\tand%0D%0Abreak.txt",
+            ),
+        ];
+
+        let mut writer = MultipartWriter::new();
+        for (name, filename, _, _) in sent {
+            writer.add_file(name, filename, None, b"content".to_vec());
+        }
+        let boundary = writer.boundary().to_owned();
+        let body = writer.into_vec().unwrap();
+        assert!(!body.windows(2).any(|window| window == b"*="));
+
+        let result = parse_multipart_form_data(&body, boundary.as_bytes(), b"utf-8").unwrap();
+        let parsed: Vec<(&str, &str)> = result
+            .iter()
+            .map(|(name, field)| match field {
+                Field::File(file) => (name, file.filename()),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        let expected: Vec<(&str, &str)> = sent
+            .into_iter()
+            .map(|(_, _, name, filename)| (name, filename))
+            .collect();
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn test_no_extended_parameters_for_form_data() {
+        assert_eq!(
+            content_disposition("välue", Some("naïve résumé.pdf")),
+            "form-data; name=\"välue\"; filename=\"naïve résumé.pdf\""
+        );
+    }
+
+    #[test]
+    fn test_random_boundary_does_not_occur_in_content() {
+        let mut writer = MultipartWriter::new();
+        let first = writer.boundary().to_owned();
+        writer.add_file("file", "a.txt", None, format!("--{}--", first));
+
+        assert_ne!(writer.boundary(), first);
+        assert!(is_token(writer.boundary()));
+
+        let second = writer.boundary().to_owned();
+        writer.add_text("field", "value");
+        assert_eq!(writer.boundary(), second);
+    }
+
+    #[test]
+    fn test_boundary_is_quoted_if_needed() {
+        let writer = MultipartWriter::with_boundary("with space").unwrap();
+        assert_eq!(
+            writer.content_type(),
+            "multipart/form-data; boundary=\"with space\""
+        );
+        for boundary in ["", "a;b", "a\"b", "a ", &"a".repeat(71)] {
+            assert!(
+                MultipartWriter::with_boundary(boundary).is_err(),
+                "{}",
+                boundary
+            );
+        }
+    }
+
+    #[test]
+    fn test_reader_in_small_chunks() {
+        let mut writer = MultipartWriter::with_boundary("boundary").unwrap();
+        writer.add_reader("file", "a.txt", None, Cursor::new(b"content".to_vec()));
+        let mut expected = Vec::new();
+        writer.into_reader().read_to_end(&mut expected).unwrap();
+
+        let mut writer = MultipartWriter::with_boundary("boundary").unwrap();
+        writer.add_reader("file", "a.txt", None, Cursor::new(b"content".to_vec()));
+        let mut reader = writer.into_reader();
+        let mut body = Vec::new();
+        let mut buf = [0u8; 3];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                read => body.extend_from_slice(&buf[..read]),
+            }
+        }
+
+        assert_eq!(body, expected);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_reader() {
+        use tokio::io::AsyncReadExt;
+
+        let mut writer = MultipartWriter::with_boundary("boundary").unwrap();
+        writer
+            .add_text("field", "value")
+            .add_async_reader("file", "a.txt", None, &b"content"[..]);
+        let mut body = Vec::new();
+        AsyncReadExt::read_to_end(&mut writer.into_reader(), &mut body)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            body,
+            b"--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Type: application/octet-stream\r\n\r\ncontent\r\n--boundary--\r\n"
+        );
+
+        let mut writer = MultipartWriter::new();
+        writer.add_async_reader("file", "a.txt", None, &b"content"[..]);
+        assert!(writer.into_vec().is_err());
+    }
+}